@@ -0,0 +1,114 @@
+; Interrupt Service Routines stubs
+;
+; Every stub pushes the same frame layout, a dummy error code when the CPU
+; doesn't push one, then its vector number, before jumping to isr_common.
+
+section .text
+bits 32
+extern interrupt_handler
+
+%macro ISR_NO_ERR 1
+isr_%1:
+	push dword 0  ; dummy error code
+	push dword %1 ; vector
+	jmp isr_common
+%endmacro
+
+%macro ISR_ERR 1
+isr_%1:
+	push dword %1 ; vector
+	jmp isr_common
+%endmacro
+
+; CPU exceptions
+ISR_NO_ERR 0
+ISR_NO_ERR 1
+ISR_NO_ERR 2
+ISR_NO_ERR 3
+ISR_NO_ERR 4
+ISR_NO_ERR 5
+ISR_NO_ERR 6
+ISR_NO_ERR 7
+ISR_ERR    8
+ISR_NO_ERR 9
+ISR_ERR    10
+ISR_ERR    11
+ISR_ERR    12
+ISR_ERR    13
+ISR_ERR    14
+ISR_NO_ERR 15
+ISR_NO_ERR 16
+ISR_ERR    17
+ISR_NO_ERR 18
+ISR_NO_ERR 19
+ISR_NO_ERR 20
+ISR_ERR    21
+ISR_NO_ERR 22
+ISR_NO_ERR 23
+ISR_NO_ERR 24
+ISR_NO_ERR 25
+ISR_NO_ERR 26
+ISR_NO_ERR 27
+ISR_NO_ERR 28
+ISR_ERR    29
+ISR_ERR    30
+ISR_NO_ERR 31
+
+; Hardware interrupts, remapped by the PICs
+%assign i 32
+%rep 16
+ISR_NO_ERR i
+%assign i i+1
+%endrep
+
+; System calls
+global isr_syscall
+isr_syscall:
+	push dword 0
+	push dword 0x80
+	jmp isr_common
+
+; Voluntary context switches
+global isr_yield
+isr_yield:
+	push dword 0
+	push dword 0x81
+	jmp isr_common
+
+isr_common:
+	; Save segments and general purpose registers
+	push ds
+	push es
+	push fs
+	push gs
+	pusha
+
+	; Load kernel data segments
+	mov ax, 0x10
+	mov ds, ax
+	mov es, ax
+	mov fs, ax
+	mov gs, ax
+	cld
+
+	push esp ; pointer to the InterruptFrame
+	call interrupt_handler
+	mov esp, eax ; InterruptFrame to restore, possibly of another task
+
+	; Restore interrupted state
+	popa
+	pop gs
+	pop fs
+	pop es
+	pop ds
+	add esp, 8 ; vector and error code
+	iret
+
+section .rodata
+global isr_stub_table
+isr_stub_table:
+%assign i 0
+%rep 48
+	dd isr_%+i
+%assign i i+1
+%endrep
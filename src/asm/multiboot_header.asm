@@ -14,6 +14,11 @@ header_start:
     dd 8    ; size
 header_end:
 
+; The kernel is linked in the higher half, 3 GiB above where it is loaded
+KERNEL_BASE equ 0xC0000000
+; Page tables of the boot mapping, 4 MiB each
+BOOT_TABLES equ 4
+
 section .bss
 global stack_high
 global stack_low
@@ -22,11 +27,57 @@ stack_low:
 	resb 16384 ; 16 KiB
 stack_high:
 
+; Map the first 16 MiB of memory twice: at their own address, for the code
+; running before the jump to the higher half, and at KERNEL_BASE.
+; Only used until the kernel sets up its own page directory.
+global boot_page_directory
+align 4096
+boot_page_directory:
+	resb 4096
+boot_page_tables:
+	resb 4096 * BOOT_TABLES
+
 section .text
 extern kernel_main
-global _start
+global start
 bits 32
-_start:
+; Entry point, at its physical address as paging is still disabled.
+; The linker script makes _start point here.
+start:
+	; Fill the boot page tables with the first frames: present, writable
+	mov edi, boot_page_tables - KERNEL_BASE
+	mov edx, 0x3
+	mov ecx, 1024 * BOOT_TABLES
+.fill_tables:
+	mov [edi], edx
+	add edi, 4
+	add edx, 0x1000
+	loop .fill_tables
+
+	; Reference them from the identity and the higher half entries
+	mov edi, boot_page_directory - KERNEL_BASE
+	mov edx, boot_page_tables - KERNEL_BASE + 0x3
+	xor ecx, ecx
+.fill_directory:
+	mov [edi + ecx * 4], edx
+	mov [edi + ecx * 4 + (KERNEL_BASE >> 22) * 4], edx
+	add edx, 0x1000
+	inc ecx
+	cmp ecx, BOOT_TABLES
+	jne .fill_directory
+
+	; Enable paging
+	mov ecx, boot_page_directory - KERNEL_BASE
+	mov cr3, ecx
+	mov ecx, cr0
+	or ecx, 0x80000000
+	mov cr0, ecx
+
+	; Absolute jump, to the linked address
+	lea ecx, [.higher_half]
+	jmp ecx
+
+.higher_half:
     mov esp, stack_high
 	xor ebp, ebp
 
@@ -37,7 +88,8 @@ _start:
 
 	; push arguments
 	; https://www.gnu.org/software/grub/manual/multiboot2/multiboot.html#Boot-information-format
-	push ebx ; physical address of the Multiboot2 information structure
+	add ebx, KERNEL_BASE ; reached through the higher half mapping
+	push ebx ; virtual address of the Multiboot2 information structure
 	push eax ; magic value for MultiBoot2 compliant bootloader, 0x36d76289
 
 	call kernel_main
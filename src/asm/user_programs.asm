@@ -0,0 +1,131 @@
+; Built-in user programs
+;
+; Those programs are copied to user pages before being run in ring 3, so
+; their code must be position independent.
+
+section .rodata
+bits 32
+
+; Spin for a while, then try to halt the CPU.
+; hlt is a privileged instruction, raising a General Protection fault in ring 3.
+global user_fault_start
+global user_fault_end
+user_fault_start:
+	mov ecx, 0x1000000
+.spin:
+	dec ecx
+	jnz .spin
+	hlt
+user_fault_end:
+
+; Greet the user, then echo a line typed on the keyboard, using a buffer
+; taken on the heap.
+global user_hello_start
+global user_hello_end
+user_hello_start:
+	call .base
+.base:
+	pop ebp ; address of .base, to reach the data position independently
+
+	; write(stdout, hello, hello_len)
+	mov eax, 2
+	mov ebx, 1
+	lea ecx, [ebp + .hello - .base]
+	mov edx, .hello_len
+	int 0x80
+
+	; sbrk(4096)
+	mov eax, 4
+	mov ebx, 4096
+	int 0x80
+	test eax, eax
+	js .error
+	mov esi, eax
+
+	; read(stdin, buffer, 256)
+	mov eax, 1
+	mov ebx, 0
+	mov ecx, esi
+	mov edx, 256
+	int 0x80
+	test eax, eax
+	js .error
+
+	; write(stdout, buffer, read)
+	mov edx, eax
+	mov eax, 2
+	mov ebx, 1
+	mov ecx, esi
+	int 0x80
+
+	; exit(getpid())
+	mov eax, 3
+	int 0x80
+	mov ebx, eax
+	mov eax, 0
+	int 0x80
+
+.error:
+	; exit(255)
+	mov eax, 0
+	mov ebx, 255
+	int 0x80
+
+.hello: db "Hello from ring 3! Type something: "
+.hello_len equ $ - .hello
+user_hello_end:
+
+; Fork a child running hello, wait for it, then exit with its status.
+global user_family_start
+global user_family_end
+user_family_start:
+	call .base
+.base:
+	pop ebp ; kept by the child, which gets a copy of the registers
+
+	; fork()
+	mov eax, 5
+	int 0x80
+	test eax, eax
+	js .error
+	jz .child
+
+	; write(stdout, parent, parent_len)
+	mov eax, 2
+	mov ebx, 1
+	lea ecx, [ebp + .parent - .base]
+	mov edx, .parent_len
+	int 0x80
+
+	; waitpid(-1, &status)
+	sub esp, 4
+	mov eax, 7
+	mov ebx, -1
+	mov ecx, esp
+	int 0x80
+	test eax, eax
+	js .error
+
+	; exit(status)
+	pop ebx
+	mov eax, 0
+	int 0x80
+
+.child:
+	; exec(hello, hello_len)
+	mov eax, 6
+	lea ebx, [ebp + .hello - .base]
+	mov ecx, .hello_len
+	int 0x80
+
+.error:
+	; exit(255)
+	mov eax, 0
+	mov ebx, 255
+	int 0x80
+
+.parent: db "Parent waiting for its child", 10
+.parent_len equ $ - .parent
+.hello: db "hello"
+.hello_len equ $ - .hello
+user_family_end:
@@ -0,0 +1,91 @@
+//! Kernel command line
+//!
+//! Options written after the kernel path in `grub.cfg`, as `key=value` or
+//! bare `key` words. They are parsed once at boot, then queried by the
+//! modules they configure.
+//!
+//! # Options
+//! - paging=on|off|pae
+//! - log=error|warn|info|debug
+//! - keyboard=qwerty|azerty
+//! - console=vga|serial
+//! - quantum=<ticks>, of the scheduler
+
+use crate::multiboot_info::MultibootInfo;
+use spin::Mutex;
+
+const MAX_OPTIONS: usize = 32;
+
+struct Options {
+    line: &'static str,
+    entries: [(&'static str, &'static str); MAX_OPTIONS],
+    len: usize,
+}
+
+static OPTIONS: Mutex<Options> = Mutex::new(Options {
+    line: "",
+    entries: [("", ""); MAX_OPTIONS],
+    len: 0,
+});
+
+/// Parse the command line given by the boot loader
+///
+/// The boot information must stay mapped, options borrow it.
+pub fn init(multiboot: MultibootInfo) {
+    let line = match multiboot.get_command_line() {
+        Some(line) => line,
+        None => return,
+    };
+    let mut options = OPTIONS.lock();
+    options.line = line;
+    for word in line.split_whitespace() {
+        if options.len == MAX_OPTIONS {
+            break;
+        }
+        let mut parts = word.splitn(2, '=');
+        let key = parts.next().unwrap();
+        let value = parts.next().unwrap_or("");
+        let len = options.len;
+        options.entries[len] = (key, value);
+        options.len += 1;
+    }
+}
+
+/// The whole command line
+pub fn line() -> &'static str {
+    OPTIONS.lock().line
+}
+
+/// Value of the option `key`, empty for a bare key
+///
+/// The last occurrence wins.
+pub fn get(key: &str) -> Option<&'static str> {
+    let options = OPTIONS.lock();
+    options.entries[..options.len]
+        .iter()
+        .rev()
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| *v)
+}
+
+/// Value of a boolean option, `default` if absent or invalid
+///
+/// A bare key is true.
+pub fn get_bool(key: &str, default: bool) -> bool {
+    match get(key) {
+        Some("") | Some("on") | Some("true") | Some("yes") | Some("1") => true,
+        Some("off") | Some("false") | Some("no") | Some("0") => false,
+        _ => default,
+    }
+}
+
+/// Call `f` with every option, in command line order
+pub fn for_each<F>(mut f: F)
+where
+    F: FnMut(&'static str, &'static str),
+{
+    let options = OPTIONS.lock();
+    for &(key, value) in options.entries[..options.len].iter() {
+        f(key, value);
+    }
+}
@@ -29,7 +29,7 @@ pub fn stack_trace(max: usize) {
 /// \<address on the stack\>: \<content of this address\>  
 /// At the end the heigth of the stack is also printed.
 pub fn dump_stack(max: usize) {
-    let stack_high: *const usize;
+    let mut stack_high: *const usize;
     let esp: *const usize;
 
     unsafe {
@@ -38,6 +38,11 @@ pub fn dump_stack(max: usize) {
             out(reg) stack_high, out(reg) esp,
             options(nostack));
     }
+    // Kernel threads run on their own stack
+    let task_stack = crate::process::table(|t| t.current_mut()?.kernel_stack_top());
+    if let Some(top) = task_stack {
+        stack_high = top as *const usize;
+    }
 
     let mut c: usize = 0;
     let mut head = esp;
@@ -91,6 +96,25 @@ pub fn dump_segment_registers() {
     );
 }
 
+use crate::idt::IdtR;
+
+/// Display the Interrupt Descriptor Table Register
+///
+/// Print it's base and limit values.
+pub fn dump_idtr() {
+    println!("{}", IdtR::current());
+}
+
+pub fn dump_idt() {
+    let mut i = 0;
+    while let Some(desc) = IdtR::get_desc(i) {
+        if desc.is_present() {
+            println!("Gate at index [{}]:\n{}\n", i, desc);
+        }
+        i += 1;
+    }
+}
+
 use crate::external_symbols::*;
 
 /// Print the addresses of symbols defined in the linker script
@@ -130,6 +154,133 @@ pub fn print_kernel_sections_addresses() {
     );
 }
 
+use crate::physical_memory_management::frame_manager;
+
 pub fn dump_bitmap() {
-    println!("{}", super::physical_memory_management::BITMAP.lock());
+    frame_manager(|bitmap| println!("{}", bitmap));
+}
+
+/// Print the free blocks of each order of the buddy allocator
+pub fn dump_buddy() {
+    frame_manager(|bitmap| println!("{}", bitmap.buddy()));
+}
+
+use crate::virtual_memory_management::current_directory;
+
+/// Print the present entries of the current page directory
+pub fn dump_page_directory() {
+    current_directory(|directory| println!("{}", directory));
+}
+
+/// Print the present entries of a page table of the current page directory
+pub fn dump_page_table(index: usize) {
+    current_directory(|directory| match directory.page_table(index) {
+        Some(table) => println!("{}", table),
+        None => println!("No page table at index {}", index),
+    });
+}
+
+/// Print the mapped pages of the current address space, merged in regions
+pub fn dump_mappings() {
+    current_directory(|directory| {
+        for region in directory.mapped_regions(0..usize::MAX) {
+            println!("{}", region);
+        }
+    });
+}
+
+use crate::multiboot_info;
+
+/// Print every tag of the boot information
+pub fn dump_multiboot() {
+    let info = match multiboot_info::boot_info() {
+        Some(info) => info,
+        None => {
+            println!("No boot information");
+            return;
+        }
+    };
+    println!("total size: {:#x}", info.total_size());
+    for tag in info {
+        let tag = unsafe { &*tag };
+        println!("tag {:2}, size {:#06x}", tag.typ(), tag.size());
+    }
+
+    if let Some(cmdline) = info.get_command_line() {
+        println!("command line: {}", cmdline);
+    }
+    if let Some(name) = info.get_bootloader_name() {
+        println!("boot loader: {}", name);
+    }
+    for module in info.get_modules() {
+        println!(
+            "module {}: {:#010x}-{:#010x}",
+            module.name(),
+            module.mod_start,
+            module.mod_end
+        );
+    }
+    if let Some(mem) = info.get_basic_memory_info() {
+        println!(
+            "memory: lower {} KiB, upper {} KiB",
+            mem.mem_lower, mem.mem_upper
+        );
+    }
+    if let Some(dev) = info.get_bios_boot_device() {
+        println!(
+            "boot device: {:#x}, partition {:#x}, sub partition {:#x}",
+            dev.biosdev, dev.partition, dev.sub_partition
+        );
+    }
+    if let Some(mem_map) = info.get_memory_map() {
+        for entry in mem_map.entries() {
+            println!(
+                "memory map: {:#010x} length {:#010x} type {}",
+                entry.base_addr, entry.length, entry.typ
+            );
+        }
+    }
+    if let Some(vbe) = info.get_vbe_info() {
+        println!("vbe mode: {:#x}", vbe.vbe_mode);
+    }
+    if let Some(fb) = info.get_framebuffer() {
+        println!(
+            "framebuffer: {:#x}, {}x{}",
+            fb.framebuffer_addr, fb.framebuffer_width, fb.framebuffer_height
+        );
+    }
+    if let Some(elf) = info.get_elf_sections() {
+        for section in elf.sections().filter(|s| s.size != 0) {
+            println!(
+                "elf section: {:#010x} size {:#08x} type {} flags {:#x}",
+                section.addr, section.size, section.typ, section.flags
+            );
+        }
+    }
+    if let Some(apm) = info.get_apm_table() {
+        println!("apm: version {:#x}, flags {:#x}", apm.version, apm.flags);
+    }
+    if let Some(rsdp) = info.get_acpi_old_rsdp() {
+        println!("acpi 1.0 rsdt: {:#010x}", rsdp.rsdt_address);
+    }
+    if let Some(rsdp) = info.get_acpi_new_rsdp() {
+        println!("acpi 2.0 xsdt: {:#x}", rsdp.xsdt_address);
+    }
+    if let Some(efi_map) = info.get_efi_memory_map() {
+        for desc in efi_map.descriptors() {
+            println!(
+                "efi memory: {:#x} pages {} type {}",
+                desc.physical_start, desc.number_of_pages, desc.typ
+            );
+        }
+    }
+    if let Some(base) = info.get_load_base_address() {
+        println!("load base address: {:#010x}", base);
+    }
+}
+
+/// Print the kernel command line options
+pub fn dump_cmdline() {
+    println!("{}", crate::cmdline::line());
+    crate::cmdline::for_each(|key, value| println!("{} = {:?}", key, value));
 }
@@ -1,6 +1,6 @@
-use crate::external_symbols::get_first_page_after_kernel;
-use crate::physical_memory_management::{BITMAP, PAGE_SIZE_4K};
-use crate::virtual_memory_management::PAGE_DIRECTORY;
+use crate::external_symbols::get_first_large_page_after_kernel;
+use crate::physical_memory_management::{frame_manager, PhysicalAddress, PAGE_SIZE_4K};
+use crate::virtual_memory_management::{self as vmm, kernel_to_physical, PageFlags};
 use core::mem;
 
 use super::Locked;
@@ -69,6 +69,21 @@ impl Heap {
         }
     }
 
+    /// Bookkeeping of a copy of this heap
+    ///
+    /// # Safety
+    ///
+    /// The heap pages must have been copied at the same addresses, in another
+    /// address space where the returned heap is exclusively used.
+    pub unsafe fn duplicate(&self) -> Heap {
+        Heap {
+            start: self.start,
+            brk: self.brk,
+            free_list: self.free_list,
+            is_supervisor: self.is_supervisor,
+        }
+    }
+
     fn get_brk(&self) -> usize {
         self.brk as usize
     }
@@ -128,44 +143,57 @@ impl Heap {
         self.free_list.ok_or(AllocError)
     }
 
-    fn sbrk(&mut self, increment: isize) -> Result<usize, AllocError> {
+    /// Move the heap break
+    ///
+    /// The break is moved by whole pages, `increment` is rounded up to the
+    /// next multiple of the page size. Return the previous break.
+    ///
+    /// With paging, the heap is a demand paged region: its pages are only
+    /// backed by frames once accessed.
+    pub fn sbrk(&mut self, increment: isize) -> Result<usize, AllocError> {
         let old_brk = self.get_brk();
         let is_neg = increment < 0;
         let mut required_pages = (increment / PAGE_SIZE_4K as isize).abs() as usize
             + (increment % PAGE_SIZE_4K as isize != 0) as usize;
+        if is_neg && required_pages * PAGE_SIZE_4K > old_brk - self.start as usize {
+            return Err(AllocError);
+        }
+
+        if vmm::current_directory(|directory| directory.is_enabled()) {
+            let new_brk = if is_neg {
+                old_brk - required_pages * PAGE_SIZE_4K
+            } else {
+                old_brk
+                    .checked_add(required_pages * PAGE_SIZE_4K)
+                    .ok_or(AllocError)?
+            };
+            let flags = match self.is_supervisor {
+                true => vmm::KERNEL_MEMORY_FLAGS,
+                false => PageFlags::USER_DATA | PageFlags::NO_EXECUTE,
+            };
+            vmm::reserve(self.start as usize, new_brk, flags).map_err(|_| AllocError)?;
+            if is_neg {
+                vmm::release(new_brk, old_brk).map_err(|_| AllocError)?;
+            }
+            self.set_brk(new_brk);
+            return Ok(old_brk);
+        }
+
         while required_pages > 0 {
             let current_brk = self.get_brk();
             self.set_brk(if is_neg {
                 let new_brk = current_brk - PAGE_SIZE_4K;
-
-                if PAGE_DIRECTORY.lock().is_enabled() {
-                    PAGE_DIRECTORY
-                        .lock()
-                        .unmap_pages(new_brk)
-                        .map_err(|_| AllocError)?;
-                } else {
-                    BITMAP.lock().free_frame(new_brk).map_err(|_| AllocError)?;
-                }
-
+                frame_manager(|bitmap| {
+                    bitmap.free_frame(kernel_to_physical(new_brk) as PhysicalAddress)
+                })
+                .map_err(|_| AllocError)?;
                 new_brk
             } else {
-                if PAGE_DIRECTORY.lock().is_enabled() {
-                    let p_add = BITMAP.lock().alloc_frame().map_err(|_| AllocError)?;
-                    PAGE_DIRECTORY
-                        .lock()
-                        .map_pages(
-                            p_add,
-                            current_brk,
-                            if self.is_supervisor { 0x3 } else { 0x7 },
-                        )
-                        .map_err(|_| AllocError)?;
-                } else {
-                    BITMAP
-                        .lock()
-                        .alloc_frame_by_address(current_brk)
-                        .map_err(|_| AllocError)?;
-                }
-
+                frame_manager(|bitmap| {
+                    bitmap
+                        .alloc_frame_by_address(kernel_to_physical(current_brk) as PhysicalAddress)
+                })
+                .map_err(|_| AllocError)?;
                 current_brk + PAGE_SIZE_4K
             });
             required_pages -= 1;
@@ -332,4 +360,4 @@ impl Drop for Heap {
 
 #[global_allocator]
 pub static KERNEL_HEAP: Locked<Heap> =
-    Locked::new(unsafe { Heap::new(get_first_page_after_kernel(), true) });
+    Locked::new(unsafe { Heap::new(get_first_large_page_after_kernel(), true) });
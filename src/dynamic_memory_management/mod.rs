@@ -6,8 +6,15 @@
 mod allocator;
 
 pub use self::allocator::{Heap, KERNEL_HEAP};
+use crate::idt;
+use core::fmt;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
 
 /// An immutable wrapper around Mutex
+///
+/// Interrupts are disabled while it is held: every task allocates, one
+/// preempted with the lock would block all the others.
 pub struct Locked<A> {
     inner: spin::Mutex<A>,
 }
@@ -19,7 +26,48 @@ impl<A> Locked<A> {
         }
     }
 
-    pub fn lock(&self) -> spin::MutexGuard<A> {
-        self.inner.lock()
+    pub fn lock(&self) -> LockedGuard<A> {
+        let were_enabled = idt::are_interrupts_enabled();
+        idt::disable_interrupts();
+        LockedGuard {
+            guard: ManuallyDrop::new(self.inner.lock()),
+            were_enabled,
+        }
+    }
+}
+
+/// Access to a `Locked` value, interrupts are restored once it is dropped
+pub struct LockedGuard<'a, A> {
+    guard: ManuallyDrop<spin::MutexGuard<'a, A>>,
+    were_enabled: bool,
+}
+
+impl<'a, A> Deref for LockedGuard<'a, A> {
+    type Target = A;
+
+    fn deref(&self) -> &A {
+        &self.guard
+    }
+}
+
+impl<'a, A> DerefMut for LockedGuard<'a, A> {
+    fn deref_mut(&mut self) -> &mut A {
+        &mut self.guard
+    }
+}
+
+impl<'a, A> Drop for LockedGuard<'a, A> {
+    fn drop(&mut self) {
+        // Released before interrupts can preempt the task
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.were_enabled {
+            idt::enable_interrupts();
+        }
+    }
+}
+
+impl<'a, A: fmt::Display> fmt::Display for LockedGuard<'a, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
     }
 }
@@ -0,0 +1,189 @@
+//! ELF32 executables
+//!
+//! Parse and validate i386 executables. Only the headers needed to load a
+//! program are read: the file header and the program headers.
+
+use core::mem::size_of;
+use core::ptr;
+
+const ELF_MAGIC: [u8; 4] = [0x7F, b'E', b'L', b'F'];
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_386: u16 = 3;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 0x1;
+const PF_W: u32 = 0x2;
+const PF_R: u32 = 0x4;
+
+#[derive(Debug)]
+pub enum ElfError {
+    TooShort,
+    BadMagic,
+    Not32Bit,
+    NotLittleEndian,
+    BadVersion,
+    NotExecutable,
+    NotI386,
+    BadProgramHeader,
+    /// A segment is bigger in the file than in memory, or out of the image
+    BadSegment,
+}
+
+/// ELF file header
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ElfHeader {
+    ident: [u8; 16],
+    typ: u16,
+    machine: u16,
+    version: u32,
+    entry: u32,
+    phoff: u32,
+    shoff: u32,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+/// ELF program header
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ProgramHeader {
+    typ: u32,
+    offset: u32,
+    vaddr: u32,
+    paddr: u32,
+    filesz: u32,
+    memsz: u32,
+    flags: u32,
+    align: u32,
+}
+
+/// A `PT_LOAD` segment, to be mapped in memory
+#[derive(Debug, Copy, Clone)]
+pub struct Segment<'a> {
+    pub virtual_address: usize,
+    /// Size in memory, the bytes after `data` are zero filled
+    pub memory_size: usize,
+    /// Content of the segment in the file
+    pub data: &'a [u8],
+    pub readable: bool,
+    pub writable: bool,
+    pub executable: bool,
+}
+
+/// A validated executable
+pub struct Elf<'a> {
+    image: &'a [u8],
+    header: ElfHeader,
+}
+
+/// Read a `T` at `offset` in `image`, if it fits
+fn read<T: Copy>(image: &[u8], offset: usize) -> Option<T> {
+    let end = offset.checked_add(size_of::<T>())?;
+    if end > image.len() {
+        return None;
+    }
+    Some(unsafe { ptr::read_unaligned(image.as_ptr().add(offset) as *const T) })
+}
+
+/// Check the ELF magic number
+pub fn is_elf(image: &[u8]) -> bool {
+    image.starts_with(&ELF_MAGIC)
+}
+
+impl<'a> Elf<'a> {
+    /// Validate the headers of an ELF32 i386 executable
+    pub fn parse(image: &'a [u8]) -> Result<Elf<'a>, ElfError> {
+        let header: ElfHeader = read(image, 0).ok_or(ElfError::TooShort)?;
+        if !is_elf(image) {
+            return Err(ElfError::BadMagic);
+        }
+        if header.ident[4] != ELFCLASS32 {
+            return Err(ElfError::Not32Bit);
+        }
+        if header.ident[5] != ELFDATA2LSB {
+            return Err(ElfError::NotLittleEndian);
+        }
+        if header.ident[6] != EV_CURRENT || header.version != EV_CURRENT as u32 {
+            return Err(ElfError::BadVersion);
+        }
+        if header.typ != ET_EXEC {
+            return Err(ElfError::NotExecutable);
+        }
+        if header.machine != EM_386 {
+            return Err(ElfError::NotI386);
+        }
+        if header.phnum > 0 && header.phentsize as usize != size_of::<ProgramHeader>() {
+            return Err(ElfError::BadProgramHeader);
+        }
+
+        let elf = Elf { image, header };
+        for i in 0..header.phnum as usize {
+            let ph = elf.program_header(i).ok_or(ElfError::BadProgramHeader)?;
+            if ph.typ == PT_LOAD {
+                let end = (ph.offset as usize).checked_add(ph.filesz as usize);
+                if ph.filesz > ph.memsz || end.map_or(true, |end| end > image.len()) {
+                    return Err(ElfError::BadSegment);
+                }
+                if (ph.vaddr as usize).checked_add(ph.memsz as usize).is_none() {
+                    return Err(ElfError::BadSegment);
+                }
+            }
+        }
+        Ok(elf)
+    }
+
+    /// Virtual address of the first instruction
+    pub fn entry(&self) -> usize {
+        self.header.entry as usize
+    }
+
+    fn program_header(&self, index: usize) -> Option<ProgramHeader> {
+        let offset =
+            (self.header.phoff as usize).checked_add(index * size_of::<ProgramHeader>())?;
+        read(self.image, offset)
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        (0..self.header.phnum as usize).filter_map(move |i| self.program_header(i))
+    }
+
+    /// Number of program headers, and size of each
+    pub fn program_headers_layout(&self) -> (usize, usize) {
+        (self.header.phnum as usize, self.header.phentsize as usize)
+    }
+
+    /// Virtual address of the program headers, when a segment loads them
+    pub fn program_headers_address(&self) -> Option<usize> {
+        let phoff = self.header.phoff as usize;
+        self.program_headers()
+            .filter(|ph| ph.typ == PT_LOAD)
+            .find(|ph| {
+                phoff >= ph.offset as usize && phoff < ph.offset as usize + ph.filesz as usize
+            })
+            .map(|ph| ph.vaddr as usize + phoff - ph.offset as usize)
+    }
+
+    /// The segments to load in memory
+    pub fn segments(&self) -> impl Iterator<Item = Segment<'a>> + '_ {
+        let image = self.image;
+        self.program_headers()
+            .filter(|ph| ph.typ == PT_LOAD)
+            .map(move |ph| Segment {
+                virtual_address: ph.vaddr as usize,
+                memory_size: ph.memsz as usize,
+                data: &image[ph.offset as usize..(ph.offset + ph.filesz) as usize],
+                readable: ph.flags & PF_R != 0,
+                writable: ph.flags & PF_W != 0,
+                executable: ph.flags & PF_X != 0,
+            })
+    }
+}
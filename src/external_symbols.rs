@@ -1,7 +1,10 @@
 //! Symbols defined in the linker script
 //!
 //! Provide information about kernel the memory layout.
-//! Used for kernel identity mapping and debug.
+//! Used for kernel mapping and debug.
+//!
+//! Addresses are virtual, in the higher half. The kernel is loaded at
+//! `KERNEL_BASE` below.
 
 extern "C" {
     pub fn kernel_start();
@@ -18,6 +21,7 @@ extern "C" {
     pub fn stack_high();
     pub fn common_bss_sep();
     pub fn first_page_after_kernel();
+    pub fn first_large_page_after_kernel();
 }
 
 const unsafe fn get_ext_symb_add(f: unsafe extern "C" fn()) -> *const usize {
@@ -33,6 +37,9 @@ pub fn get_kernel_end() -> *const usize {
 pub const fn get_first_page_after_kernel() -> *const usize {
     unsafe { get_ext_symb_add(first_page_after_kernel) }
 }
+pub const fn get_first_large_page_after_kernel() -> *const usize {
+    unsafe { get_ext_symb_add(first_large_page_after_kernel) }
+}
 pub fn get_section_text_start() -> *const usize {
     unsafe { get_ext_symb_add(section_text_start) }
 }
@@ -5,7 +5,9 @@ mod tss;
 
 pub use self::segment_descriptor::SegmentDescriptor;
 use self::tss::Tss;
+use crate::virtual_memory_management::physical_to_kernel;
 use core::fmt;
+use spin::Mutex;
 
 extern "C" {
     fn memcpy(dst: *mut u8, src: *const u8, size: usize);
@@ -29,9 +31,22 @@ impl fmt::Display for GdtR {
     }
 }
 
-pub const GDTBASE: usize = 0x00000800;
+pub const GDTBASE: usize = physical_to_kernel(0x00000800);
 const GDTLEN: usize = 8;
 
+pub const KERNEL_CODE_SELECTOR: u16 = 0x08;
+pub const KERNEL_DATA_SELECTOR: u16 = 0x10;
+pub const KERNEL_STACK_SELECTOR: u16 = 0x18;
+/// User segments selectors, with a requested privilege level of 3
+pub const USER_CODE_SELECTOR: u16 = 0x20 | 0x3;
+pub const USER_DATA_SELECTOR: u16 = 0x28 | 0x3;
+pub const USER_STACK_SELECTOR: u16 = 0x30 | 0x3;
+
+/// The Task State Segment
+///
+/// Only used to provide the kernel stack when an interrupt occurs in ring 3.
+static TSS: Mutex<Tss> = Mutex::new(Tss::new(0));
+
 use core::mem::size_of;
 
 impl GdtR {
@@ -80,7 +95,8 @@ pub fn init() {
     unsafe {
         asm!("lea {}, [stack_high]", out(reg) stack_high, options(nostack));
     }
-    let tss = Tss::new(stack_high);
+    set_kernel_stack(stack_high);
+    let tss = &*TSS.lock() as *const Tss as u32;
 
     let descriptors: [SegmentDescriptor; GDTLEN] = [
         SegmentDescriptor::new(0x0, 0x0, 0x0, 0x0), // 0x0 Not used
@@ -90,7 +106,7 @@ pub fn init() {
         SegmentDescriptor::new(0x0, 0xFFFFF, 0xFE, 0xC), // 0x20 User Code
         SegmentDescriptor::new(0x0, 0xFFFFF, 0xF2, 0xC), // 0x28 User Data
         SegmentDescriptor::new(0x0, 0x0, 0xF6, 0xC), // 0x30 User Stack
-        SegmentDescriptor::new(&tss as *const Tss as u32, 0x67, 0xE9, 0x00), // 0x38 TSS
+        SegmentDescriptor::new(tss, 0x67, 0xE9, 0x00), // 0x38 TSS
     ];
 
     let mut gdt = Gdt {
@@ -100,6 +116,18 @@ pub fn init() {
     gdt.init(&descriptors);
 }
 
+/// Set the stack used when an interrupt occurs in ring 3
+///
+/// `esp0` is the top of a kernel stack, which must not be in use.
+pub fn set_kernel_stack(esp0: u32) {
+    TSS.lock().set_esp0(esp0);
+}
+
+/// Stack used when an interrupt occurs in ring 3
+pub fn kernel_stack() -> u32 {
+    TSS.lock().esp0()
+}
+
 struct Gdt {
     base: usize,
     len: usize,
@@ -41,7 +41,7 @@ pub struct Tss {
 }
 
 impl Tss {
-    pub fn new(esp0: u32) -> Tss {
+    pub const fn new(esp0: u32) -> Tss {
         Tss {
             link: 0,
             link_h: 0,
@@ -83,4 +83,13 @@ impl Tss {
             oipb_offset: 0x68, // 0x68 = 104 = size_of(Tss)
         }
     }
+
+    /// Stack pointer loaded when an interrupt switches to ring 0
+    pub fn esp0(&self) -> u32 {
+        self.esp0
+    }
+
+    pub fn set_esp0(&mut self, esp0: u32) {
+        self.esp0 = esp0;
+    }
 }
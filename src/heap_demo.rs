@@ -10,7 +10,7 @@ use alloc::vec;
 use core::alloc::{Allocator, GlobalAlloc, Layout};
 use core::ptr::NonNull;
 use dynamic_memory_management::{Heap, Locked, KERNEL_HEAP};
-use physical_memory_management::BITMAP;
+use physical_memory_management::frame_manager;
 
 fn box_demo() {
     {
@@ -78,7 +78,7 @@ fn infinite_allocate_demo() {
         println!("{} {:p} {}", c, x, my_allocator.lock());
         c += 1;
     }
-    println!("{}", BITMAP.lock());
+    frame_manager(|bitmap| println!("{}", bitmap));
 }
 
 fn infinite_alloc_demo() {
@@ -105,7 +105,7 @@ fn infinite_box_demo() {
 fn toobig_vec_demo() {
     let _ = vec![0; 1000 * 1000 * 1024];
     println!("{}", KERNEL_HEAP.lock());
-    println!("{}", BITMAP.lock());
+    frame_manager(|bitmap| println!("{}", bitmap));
 }
 
 pub fn demo() {
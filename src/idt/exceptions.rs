@@ -0,0 +1,125 @@
+//! CPU exceptions
+//!
+//! Report the faulting state, then kill the faulting user process or hand off
+//! to the panic handler. Page faults in demand paged regions, or on copy on
+//! write pages, are resolved instead.
+
+use super::InterruptFrame;
+use crate::process;
+use crate::virtual_memory_management as vmm;
+use crate::writer::WRITER;
+
+static EXCEPTION_NAMES: [&str; 32] = [
+    "Divide Error",
+    "Debug",
+    "Non-maskable Interrupt",
+    "Breakpoint",
+    "Overflow",
+    "BOUND Range Exceeded",
+    "Invalid Opcode",
+    "Device Not Available",
+    "Double Fault",
+    "Coprocessor Segment Overrun",
+    "Invalid TSS",
+    "Segment Not Present",
+    "Stack-Segment Fault",
+    "General Protection",
+    "Page Fault",
+    "Reserved",
+    "x87 Floating-Point Exception",
+    "Alignment Check",
+    "Machine Check",
+    "SIMD Floating-Point Exception",
+    "Virtualization Exception",
+    "Control Protection Exception",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Hypervisor Injection Exception",
+    "VMM Communication Exception",
+    "Security Exception",
+    "Reserved",
+];
+
+const PAGE_FAULT: u32 = 14;
+
+/// Page fault error code bits
+const PF_PRESENT: u32 = 0x1;
+const PF_WRITE: u32 = 0x2;
+const PF_USER: u32 = 0x4;
+const PF_INSTRUCTION: u32 = 0x10;
+
+fn read_cr2() -> usize {
+    let cr2: usize;
+    unsafe {
+        asm!("mov {}, cr2", out(reg) cr2, options(nomem, nostack));
+    }
+    cr2
+}
+
+/// Describe the access that caused a page fault
+fn print_page_fault(error_code: u32) {
+    let access = match error_code {
+        e if e & PF_INSTRUCTION != 0 => "execute",
+        e if e & PF_WRITE != 0 => "write",
+        _ => "read",
+    };
+    let mode = match error_code & PF_USER != 0 {
+        true => "user",
+        false => "supervisor",
+    };
+    let page = match error_code {
+        e if e & PF_PRESENT == 0 => "page not present",
+        e if e & PF_USER != 0 => "protection violation",
+        e if e & PF_INSTRUCTION != 0 => "protection violation, page not executable",
+        e if e & PF_WRITE != 0 => "protection violation, page read only",
+        _ => "protection violation",
+    };
+    println!("{} access in {} mode, {}", access, mode, page);
+}
+
+/// Back a demand paged page, or copy a copy on write one
+///
+/// Return true if the access can be retried.
+fn resolve_page_fault(error_code: u32) -> bool {
+    let address = read_cr2();
+    let write = error_code & PF_WRITE != 0;
+    match error_code & PF_PRESENT != 0 {
+        false => vmm::handle_fault(address, write, error_code & PF_USER != 0),
+        true if write => vmm::handle_write_fault(address),
+        true => false,
+    }
+}
+
+pub fn handle(frame: &mut InterruptFrame) {
+    if frame.vector == PAGE_FAULT && resolve_page_fault(frame.error_code) {
+        return;
+    }
+
+    // The fault may have occured while the screen was being written
+    if WRITER.is_locked() {
+        unsafe { WRITER.force_unlock() };
+    }
+
+    let name = EXCEPTION_NAMES[frame.vector as usize];
+    println!();
+    println!("EXCEPTION: {} (vector {})", name, frame.vector);
+    println!("error code: {:#010x}", frame.error_code);
+    if frame.vector == PAGE_FAULT {
+        println!("cr2: {:#010x}", read_cr2());
+        print_page_fault(frame.error_code);
+        if let Some(section) = vmm::section_name(read_cr2()) {
+            println!("in the kernel {}", section);
+        }
+    }
+    println!("{}", frame);
+    if frame.is_from_user() {
+        println!("User process killed");
+        process::exit(128 + frame.vector as usize);
+        return;
+    }
+    panic!("unrecoverable CPU exception: {}", name);
+}
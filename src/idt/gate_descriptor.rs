@@ -0,0 +1,71 @@
+/// Gate Descriptor
+///
+/// Memory layout of an interrupt or trap gate entry of the IDT.
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C, packed)]
+pub struct GateDescriptor {
+    offset0_15: u16,
+    selector: u16,
+    _reserved: u8,
+    type_attr: u8,
+    offset16_31: u16,
+}
+
+impl GateDescriptor {
+    pub const fn new(offset: u32, selector: u16, type_attr: u8) -> GateDescriptor {
+        GateDescriptor {
+            offset0_15: (offset & 0xffff) as u16,
+            selector,
+            _reserved: 0,
+            type_attr,
+            offset16_31: ((offset & 0xffff0000) >> 16) as u16,
+        }
+    }
+
+    /// A non present gate
+    ///
+    /// Raising its vector trigger a General Protection fault.
+    pub const fn missing() -> GateDescriptor {
+        GateDescriptor::new(0x0, 0x0, 0x0)
+    }
+
+    fn offset(&self) -> usize {
+        self.offset0_15 as usize | (self.offset16_31 as usize) << 16
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.type_attr & 0x80 != 0
+    }
+
+    fn privilege(&self) -> u8 {
+        (self.type_attr & 0x60) >> 5
+    }
+
+    fn gate_type(&self) -> u8 {
+        self.type_attr & 0xf
+    }
+}
+
+use core::fmt;
+
+impl fmt::Display for GateDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let selector = self.selector;
+        write!(
+            f,
+            "offset: {:#010x}, selector: {:#06x}\npresent: {}, privilege: {}, type: {}",
+            self.offset(),
+            selector,
+            self.is_present(),
+            self.privilege(),
+            match self.gate_type() {
+                0x5 => "32bit Task",
+                0x6 => "16bit Interrupt",
+                0x7 => "16bit Trap",
+                0xE => "32bit Interrupt",
+                0xF => "32bit Trap",
+                _ => "Invalid",
+            }
+        )
+    }
+}
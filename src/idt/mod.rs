@@ -0,0 +1,272 @@
+//! Interrupt Descriptor Table
+//!
+//! Route every interrupt vector to the assembly stubs defined in
+//! `interrupts.asm`, which all end up in `interrupt_handler`.
+
+mod exceptions;
+mod gate_descriptor;
+
+pub use self::gate_descriptor::GateDescriptor;
+use crate::pic;
+use crate::process::scheduler;
+use core::fmt;
+use spin::Mutex;
+
+/// IDT Register
+///
+/// Memory layout of the 48 bit IDT register
+#[derive(Debug, Clone, Copy, Default)]
+#[repr(C, packed)]
+pub struct IdtR {
+    pub limit: u16,
+    pub base: usize,
+}
+
+impl fmt::Display for IdtR {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let base = self.base;
+        let limit = self.limit;
+        write!(f, "base: {:#010x}, limit: {:#06x}", base, limit)
+    }
+}
+
+const IDTLEN: usize = 256;
+const N_EXCEPTIONS: usize = 32;
+const N_STUBS: usize = 48;
+/// Vector raised by user programs with `int 0x80`
+pub const SYSCALL_VECTOR: usize = 0x80;
+/// Vector raised by the kernel with `int 0x81` to give the CPU away
+pub const YIELD_VECTOR: usize = 0x81;
+
+use core::mem::size_of;
+
+impl IdtR {
+    pub fn current() -> IdtR {
+        let idtr = IdtR::default();
+
+        unsafe {
+            asm!("sidt [{}]", in(reg) &idtr as *const _);
+        }
+
+        idtr
+    }
+
+    pub fn get_desc(index: usize) -> Option<GateDescriptor> {
+        let idtr = IdtR::current();
+
+        if index >= (idtr.limit as usize + 1) / size_of::<GateDescriptor>() {
+            return None;
+        }
+
+        Some(unsafe { *((idtr.base as *const GateDescriptor).add(index)) })
+    }
+}
+
+/// Registers state at the time of the interrupt
+///
+/// Pushed on the stack by the CPU and the `isr_common` stub, from the
+/// last pushed to the first one.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptFrame {
+    pub edi: u32,
+    pub esi: u32,
+    pub ebp: u32,
+    pub esp: u32,
+    pub ebx: u32,
+    pub edx: u32,
+    pub ecx: u32,
+    pub eax: u32,
+    pub gs: u32,
+    pub fs: u32,
+    pub es: u32,
+    pub ds: u32,
+    pub vector: u32,
+    pub error_code: u32,
+    pub eip: u32,
+    pub cs: u32,
+    pub eflags: u32,
+    /// Only pushed when the interrupted code was running in ring 3
+    pub user_esp: u32,
+    /// Only pushed when the interrupted code was running in ring 3
+    pub user_ss: u32,
+}
+
+impl InterruptFrame {
+    /// Check if the interrupted code was running in ring 3
+    pub fn is_from_user(&self) -> bool {
+        self.cs & 0x3 == 0x3
+    }
+}
+
+impl fmt::Display for InterruptFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "eip: {:#010x}, cs: {:#06x}, eflags: {:#010x}",
+            self.eip,
+            self.cs & 0xFFFF,
+            self.eflags
+        )?;
+        writeln!(
+            f,
+            "eax: {:#010x}, ebx: {:#010x}, ecx: {:#010x}, edx: {:#010x}",
+            self.eax, self.ebx, self.ecx, self.edx
+        )?;
+        write!(
+            f,
+            "esi: {:#010x}, edi: {:#010x}, ebp: {:#010x}, esp: {:#010x}",
+            self.esi, self.edi, self.ebp, self.esp
+        )?;
+        if self.is_from_user() {
+            write!(
+                f,
+                "\nuser esp: {:#010x}, user ss: {:#06x}",
+                self.user_esp,
+                self.user_ss & 0xFFFF
+            )?;
+        }
+        Ok(())
+    }
+}
+
+extern "C" {
+    static isr_stub_table: [u32; N_STUBS];
+    fn isr_syscall();
+    fn isr_yield();
+}
+
+static mut IDT: [GateDescriptor; IDTLEN] = [GateDescriptor::missing(); IDTLEN];
+
+/// Initialize the Interrupt Descriptor Table
+///
+/// The 32 CPU exceptions vectors and the 16 IRQs vectors are set as interrupt
+/// gates, running in the kernel code segment. The system call vector is a
+/// trap gate, reachable from ring 3: system calls run with interrupts enabled.
+/// The yield vector is an interrupt gate only reachable from the kernel. All
+/// the other vectors are left non present.
+pub fn init() {
+    unsafe {
+        for (vector, &offset) in isr_stub_table.iter().enumerate() {
+            IDT[vector] = GateDescriptor::new(offset, 0x08, 0x8E);
+        }
+        IDT[SYSCALL_VECTOR] = GateDescriptor::new(isr_syscall as usize as u32, 0x08, 0xEF);
+        IDT[YIELD_VECTOR] = GateDescriptor::new(isr_yield as usize as u32, 0x08, 0x8E);
+        load_to_reg();
+    }
+}
+
+unsafe fn load_to_reg() {
+    let idtr = IdtR {
+        limit: (size_of::<GateDescriptor>() * IDTLEN - 1) as u16,
+        base: IDT.as_ptr() as usize,
+    };
+    asm!("lidt [{}]", in(reg) &idtr, options(nostack));
+}
+
+/// An interrupt handler
+///
+/// Run with interrupts disabled, the system call one apart. It must not take
+/// locks that could be held by the interrupted code.
+pub type Handler = fn(&mut InterruptFrame);
+
+static HANDLERS: Mutex<[Option<Handler>; IDTLEN]> = Mutex::new([None; IDTLEN]);
+
+/// Set the function called when `vector` is raised
+pub fn register_handler(vector: usize, handler: Handler) {
+    assert!(
+        vector >= N_EXCEPTIONS && vector < IDTLEN,
+        "cannot register an handler for vector {}",
+        vector
+    );
+    without_interrupts(|| HANDLERS.lock()[vector] = Some(handler));
+}
+
+/// Common interrupt entry point
+///
+/// Called by the `isr_common` stub with a pointer to the saved registers.
+/// Return a pointer to the registers to restore, which belong to another task
+/// after a context switch.
+#[no_mangle]
+pub extern "C" fn interrupt_handler(frame: &mut InterruptFrame) -> *mut InterruptFrame {
+    let vector = frame.vector as usize;
+    if vector < N_EXCEPTIONS {
+        exceptions::handle(frame);
+    } else {
+        let irq = pic::irq_of(vector);
+        if irq.map_or(false, pic::is_spurious) {
+            return frame;
+        }
+
+        // System calls run with interrupts enabled
+        let handler = without_interrupts(|| HANDLERS.lock()[vector]);
+        match handler {
+            Some(handler) => handler(frame),
+            None => println!("Unhandled interrupt: {:#04x}", vector),
+        }
+
+        if let Some(irq) = irq {
+            pic::end_of_interrupt(irq);
+        }
+    }
+
+    // Nothing may run on the stack of a task being switched away from
+    disable_interrupts();
+    scheduler::schedule(frame)
+}
+
+/// Allow maskable interrupts
+pub fn enable_interrupts() {
+    unsafe {
+        asm!("sti", options(nomem, nostack));
+    }
+}
+
+/// Prevent maskable interrupts
+pub fn disable_interrupts() {
+    unsafe {
+        asm!("cli", options(nomem, nostack));
+    }
+}
+
+/// Check the interrupt flag of EFLAGS
+pub fn are_interrupts_enabled() -> bool {
+    let eflags: u32;
+    unsafe {
+        asm!("pushfd
+            pop {}", out(reg) eflags, options(nomem));
+    }
+    eflags & 0x200 != 0
+}
+
+/// Enable interrupts and wait for the next one
+///
+/// `sti` only takes effect after the following instruction, so no interrupt
+/// can be missed between the two.
+pub fn enable_interrupts_and_halt() {
+    unsafe {
+        asm!(
+            "sti
+            hlt",
+            options(nomem, nostack)
+        );
+    }
+}
+
+/// Run a closure with interrupts disabled
+///
+/// The previous interrupt flag state is restored afterward.
+pub fn without_interrupts<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    let were_enabled = are_interrupts_enabled();
+    if were_enabled {
+        disable_interrupts();
+    }
+    let ret = f();
+    if were_enabled {
+        enable_interrupts();
+    }
+    ret
+}
@@ -0,0 +1,74 @@
+//! Initial ramdisk
+//!
+//! Files loaded by GRUB with `module2` lines, named by their command line.
+//! Their frames are reserved when memory is set up, they are then mapped in
+//! the kernel space to be read as byte slices.
+
+use crate::multiboot_info::MultibootInfo;
+use crate::physical_memory_management::{PhysicalAddress, PAGE_SIZE_4K};
+use crate::virtual_memory_management::{current_directory, physical_to_kernel, PageFlags};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::slice;
+use spin::Mutex;
+
+/// Kernel space addresses where the modules are mapped
+const INITRD_WINDOW: usize = 0xF0000000;
+
+/// A file of the initial ramdisk
+#[derive(Debug, Clone)]
+pub struct File {
+    pub name: String,
+    pub data: &'static [u8],
+}
+
+static FILES: Mutex<Vec<File>> = Mutex::new(Vec::new());
+
+/// Make the boot modules available
+///
+/// Memory must be set up, the modules frames reserved.
+pub fn init(multiboot: MultibootInfo) {
+    let mut window = INITRD_WINDOW;
+    let mut files = FILES.lock();
+
+    for module in multiboot.get_modules() {
+        let first_frame = module.mod_start as usize & !0xFFF;
+        let offset = module.mod_start as usize - first_frame;
+        // Not held while the files grow, the heap may fault to be backed
+        let address = if current_directory(|directory| directory.is_enabled()) {
+            let start = window;
+            let mut frame = first_frame;
+            while frame < module.mod_end as usize {
+                current_directory(|directory| {
+                    directory.map_pages(frame as PhysicalAddress, window, PageFlags::KERNEL_DATA)
+                })
+                .unwrap();
+                frame += PAGE_SIZE_4K;
+                window += PAGE_SIZE_4K;
+            }
+            start + offset
+        } else {
+            // Reached through the bootstrap mapping
+            physical_to_kernel(module.mod_start as usize)
+        };
+
+        files.push(File {
+            name: String::from(module.name()),
+            data: unsafe { slice::from_raw_parts(address as *const u8, module.len()) },
+        });
+    }
+}
+
+/// Content of the file `name`
+pub fn get(name: &str) -> Option<&'static [u8]> {
+    FILES
+        .lock()
+        .iter()
+        .find(|file| file.name == name)
+        .map(|file| file.data)
+}
+
+/// Every file of the ramdisk
+pub fn files() -> Vec<File> {
+    FILES.lock().clone()
+}
@@ -4,6 +4,7 @@
 //! - Shift key
 //! - Control key
 //! - Left and right arrow
+//! - QWERTY and AZERTY layouts
 
 mod sets;
 
@@ -109,6 +110,58 @@ pub enum Key {
     None,
 }
 
+/// Keys placement
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Layout {
+    Qwerty,
+    /// French layout, accented letters are typed without their accent
+    Azerty,
+}
+
+impl core::str::FromStr for Layout {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Layout, ()> {
+        match s {
+            "qwerty" => Ok(Layout::Qwerty),
+            "azerty" => Ok(Layout::Azerty),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Keys of the AZERTY layout differing from QWERTY, without and with shift
+fn azerty_key(scan_code: usize) -> Option<(char, char)> {
+    Some(match scan_code {
+        0x0E => ('2', '~'),
+        0x15 => ('a', 'A'),
+        0x16 => ('&', '1'),
+        0x1A => ('w', 'W'),
+        0x1C => ('q', 'Q'),
+        0x1D => ('z', 'Z'),
+        0x1E => ('e', '2'),
+        0x25 => ('\'', '4'),
+        0x26 => ('"', '3'),
+        0x2E => ('(', '5'),
+        0x36 => ('-', '6'),
+        0x3A => (',', '?'),
+        0x3D => ('e', '7'),
+        0x3E => ('_', '8'),
+        0x41 => (';', '.'),
+        0x45 => ('a', '0'),
+        0x46 => ('c', '9'),
+        0x49 => (':', '/'),
+        0x4A => ('!', '!'),
+        0x4C => ('m', 'M'),
+        0x4E => (')', ']'),
+        0x52 => ('u', '%'),
+        0x54 => ('^', '"'),
+        0x5B => ('$', '*'),
+        0x5D => ('*', 'u'),
+        _ => return None,
+    })
+}
+
 /// Scan code interpreter
 ///
 /// Handle scan codes according to it's keymap and state. Output the
@@ -119,6 +172,7 @@ pub enum Key {
 pub struct Keyboard {
     state: State,
     keymap: KeyMap,
+    layout: Layout,
 }
 
 impl Keyboard {
@@ -183,7 +237,16 @@ impl Keyboard {
         }
     }
 
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+    }
+
     fn get_char(&self, scan_code: usize) -> char {
+        if self.layout == Layout::Azerty {
+            if let Some((c, shifted)) = azerty_key(scan_code) {
+                return if self.state.shift() { shifted } else { c };
+            }
+        }
         if !self.state.shift() {
             self.keymap.key_array[scan_code]
         } else {
@@ -201,4 +264,5 @@ pub static KEYBOARD: Mutex<Keyboard> = Mutex::new(Keyboard {
         shift_key_array: sets::SET_2_SHIFT,
     },
     state: State(0),
+    layout: Layout::Qwerty,
 });
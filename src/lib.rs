@@ -6,15 +6,40 @@
 //!
 //! # Features
 //! - Global Descriptor Table
+//! - Interrupt Descriptor Table and CPU exceptions handling
 //! - VGA Screen
-//! - Keyboard input
+//! - Interrupt driven keyboard input
 //! - Basic command line interpreter
 //! - Debug utilities
 //! - Power management
+//! - Timer and sleep
+//! - Real-time clock
 //! - Physical memory management
+//! - Frame bitmap sized from the memory map, with a summary level
+//! - Buddy allocator for contiguous physical frames
+//! - Low, DMA and normal memory zones
 //! - Paging & virtual memory management
+//! - Higher half kernel
+//! - Demand paging of heaps and stacks
+//! - Copy-on-write page sharing
+//! - Address translation and page table introspection
+//! - Typed page flags and page protection
+//! - 4 MiB pages for the kernel image
+//! - PAE paging beyond 4 GiB, selectable at boot
+//! - Read-only kernel code, no-execute data, heaps and stacks
 //! - Unique Kernel heap
 //! - Multiple user heaps
+//! - Ring 3 user mode
+//! - Processes with isolated address spaces
+//! - Preemptive round-robin scheduler
+//! - Kernel threads and wait queues
+//! - fork, exec, wait and kill
+//! - ELF32 executable loader
+//! - Initial ramdisk from Multiboot2 modules
+//! - Multiboot2 boot information parsing
+//! - Kernel command line options
+//! - Serial console and log levels
+//! - System calls
 //! - alloc crate compatibility
 //! - Dynamic framebuffer and RAM size
 
@@ -40,24 +65,37 @@ use core::panic::PanicInfo;
 
 #[macro_use]
 pub mod writer;
+#[macro_use]
+pub mod log;
+pub mod cmdline;
 pub mod debug;
 pub mod dynamic_memory_management;
+pub mod elf;
 pub mod external_symbols;
 pub mod gdt;
 pub mod heap_demo;
+pub mod idt;
+pub mod initrd;
 pub mod io_port;
 pub mod keyboard;
 pub mod multiboot_info;
 pub mod physical_memory_management;
+pub mod pic;
 pub mod power_management;
+pub mod process;
 pub mod ps2;
+pub mod ring_buffer;
+pub mod serial;
 pub mod shell;
+pub mod syscall;
+pub mod time;
+pub mod user_mode;
 pub mod virtual_memory_management;
 
 use keyboard::{Command, KEYBOARD};
 use multiboot_info::MultibootInfo;
 use ps2::PS2;
-use writer::WRITER;
+use virtual_memory_management::PagingMode;
 
 /// This function is called on panic.
 #[panic_handler]
@@ -82,34 +120,97 @@ fn init(magic_number: usize, multiboot: MultibootInfo) {
     // Framebuffer
     writer::init(multiboot);
 
+    // Boot options
+    cmdline::init(multiboot);
+    if let Some(level) = cmdline::get("log") {
+        match level.parse() {
+            Ok(level) => log::set_level(level),
+            Err(_) => warn!("Unknown log level: {}", level),
+        }
+    }
+    if cmdline::get("console") == Some("serial") {
+        serial::init();
+        writer::set_console(writer::Console::Serial);
+    }
+
     let tag = multiboot.get_framebuffer().unwrap();
-    println!("{} {}", tag.framebuffer_width, tag.framebuffer_height);
+    debug!("{} {}", tag.framebuffer_width, tag.framebuffer_height);
 
     // Global Descriptor Table
     gdt::init();
 
+    // Interrupt Descriptor Table
+    idt::init();
+
     // Paging
-    virtual_memory_management::init(true, multiboot);
+    let paging = match cmdline::get("paging").map(|mode| (mode, mode.parse())) {
+        Some((_, Ok(paging))) => paging,
+        Some((mode, Err(_))) => {
+            warn!("Unknown paging mode: {}", mode);
+            PagingMode::Legacy
+        }
+        None => PagingMode::Legacy,
+    };
+    virtual_memory_management::init(paging, multiboot);
+
+    // Boot modules
+    initrd::init(multiboot);
+    multiboot_info::save(multiboot);
+
+    // System calls
+    syscall::init();
+
+    // Programmable Interrupt Controller
+    pic::init();
+
+    // Timer
+    time::init(time::TickSource::Pit);
+    idt::enable_interrupts();
+
+    // Multitasking
+    process::scheduler::init();
+    if let Some(quantum) = cmdline::get("quantum") {
+        match quantum.parse() {
+            Ok(ticks) if ticks > 0 => process::scheduler::set_quantum(ticks),
+            _ => warn!("Invalid scheduler quantum: {}", quantum),
+        }
+    }
 
     // Keyboard input
     PS2.lock().init();
+    if let Some(layout) = cmdline::get("keyboard") {
+        match layout.parse() {
+            Ok(layout) => KEYBOARD.lock().set_layout(layout),
+            Err(_) => warn!("Unknown keyboard layout: {}", layout),
+        }
+    }
 }
 
 /// The kernel entry point.
 ///
 /// This is the function called by grub after reading the multiboot header.
-/// It first initializes hardwares and wait for keyboard inputs to display on
-/// screen.
+/// It first initializes hardwares, then hands the keyboard inputs to the
+/// console kernel thread.
 #[no_mangle]
 pub extern "C" fn kernel_main(magic_number: usize, p_multiboot_info: MultibootInfo) {
     init(magic_number, p_multiboot_info);
-    debug::print_kernel_sections_addresses();
+    if log::enabled(log::Level::Debug) {
+        debug::print_kernel_sections_addresses();
+    }
+    process::kthread::spawn("console", console).join();
+}
+
+/// Display keyboard inputs on screen, and run the shell commands
+///
+/// The thread is blocked while no scan code is waiting to be handled.
+fn console() {
     loop {
-        let c = PS2.lock().read();
-        match KEYBOARD.lock().handle_scan_code(c as usize) {
+        let c = ps2::next_scan_code();
+        let key = KEYBOARD.lock().handle_scan_code(c as usize);
+        match key {
             keyboard::Key::Character(c) if c != 0x0 as char => print!("{}", c),
-            keyboard::Key::Command(Command::Left) => WRITER.lock().as_mut().unwrap().left(),
-            keyboard::Key::Command(Command::Right) => WRITER.lock().as_mut().unwrap().right(),
+            keyboard::Key::Command(Command::Left) => writer::screen(|screen| screen.left()),
+            keyboard::Key::Command(Command::Right) => writer::screen(|screen| screen.right()),
             keyboard::Key::Command(Command::Enter) => shell::execute(),
             keyboard::Key::Command(Command::LastCommand) => shell::load_last_command(),
             _ => (),
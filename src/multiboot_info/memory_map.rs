@@ -1,10 +1,12 @@
+use super::Entries;
+
 #[repr(C)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug)]
 pub struct MemoryMapTag {
     typ: usize,
     size: usize,
     entry_size: u32,
-    pub entry_version: u32,
+    entry_version: u32,
 }
 
 #[repr(C)]
@@ -12,45 +14,17 @@ pub struct MemoryMapTag {
 pub struct MemoryMapEntry {
     pub base_addr: u64,
     pub length: u64,
+    /// 1 for available RAM, 3 for ACPI reclaimable, 4 to preserve on hibernation
     pub typ: u32,
     _reserved: u32,
 }
 
-#[derive(Debug)]
-pub struct MemoryMap {
-    pub inner: *const MemoryMapTag,
-}
-
-#[derive(Debug, Copy, Clone)]
-pub struct MemoryMapIntoIter {
-    inner: *const MemoryMapEntry,
-    end: *const MemoryMapEntry,
-}
-
-impl IntoIterator for MemoryMap {
-    type Item = *const MemoryMapEntry;
-    type IntoIter = MemoryMapIntoIter;
-
-    fn into_iter(self) -> Self::IntoIter {
-        unsafe {
-            MemoryMapIntoIter {
-                inner: self.inner.offset(1) as *const MemoryMapEntry,
-                end: (self.inner as usize + (*self.inner).size as usize) as *const MemoryMapEntry,
-            }
-        }
+impl MemoryMapTag {
+    pub fn entry_version(&self) -> u32 {
+        self.entry_version
     }
-}
-
-impl Iterator for MemoryMapIntoIter {
-    type Item = *const MemoryMapEntry;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.inner >= self.end {
-            None
-        } else {
-            let ret = self.inner;
-            self.inner = unsafe { self.inner.offset(1) };
-            Some(ret)
-        }
+    pub fn entries(&'static self) -> Entries<MemoryMapEntry> {
+        unsafe { Entries::new(self, self.size, self.entry_size as usize) }
     }
 }
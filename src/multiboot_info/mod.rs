@@ -1,8 +1,40 @@
+//! Multiboot2 boot information
+//!
+//! The boot loader gives the kernel a list of tags, each describing a part of
+//! the machine or of the boot. They are reached through typed accessors,
+//! valid as long as the boot information stays mapped.
+
 mod framebuffer;
 mod memory_map;
+mod module;
+mod tags;
+
+pub use self::framebuffer::*;
+pub use self::memory_map::*;
+pub use self::module::ModuleTag;
+pub use self::tags::*;
+use core::marker::PhantomData;
+use core::mem::size_of;
+use core::slice;
+use core::str;
+use spin::Mutex;
 
-use self::framebuffer::*;
-use self::memory_map::*;
+/// Tag types
+pub const TAG_END: usize = 0;
+pub const TAG_COMMAND_LINE: usize = 1;
+pub const TAG_BOOTLOADER_NAME: usize = 2;
+pub const TAG_MODULE: usize = 3;
+pub const TAG_BASIC_MEMORY_INFO: usize = 4;
+pub const TAG_BIOS_BOOT_DEVICE: usize = 5;
+pub const TAG_MEMORY_MAP: usize = 6;
+pub const TAG_VBE_INFO: usize = 7;
+pub const TAG_FRAMEBUFFER: usize = 8;
+pub const TAG_ELF_SECTIONS: usize = 9;
+pub const TAG_APM_TABLE: usize = 10;
+pub const TAG_ACPI_OLD_RSDP: usize = 14;
+pub const TAG_ACPI_NEW_RSDP: usize = 15;
+pub const TAG_EFI_MEMORY_MAP: usize = 17;
+pub const TAG_LOAD_BASE_ADDRESS: usize = 21;
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
@@ -10,6 +42,8 @@ pub struct MultibootInfo {
     pub inner: *const FixedPart,
 }
 
+unsafe impl Send for MultibootInfo {}
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct FixedPart {
@@ -29,11 +63,30 @@ pub struct Tag {
     size: usize,
 }
 
+impl Tag {
+    pub fn typ(&self) -> usize {
+        self.typ
+    }
+
+    /// Size of the tag, header included
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The tag payload, as a NUL terminated string
+    unsafe fn string(&self) -> &'static str {
+        let start = (self as *const Tag).offset(1) as *const u8;
+        let bytes = slice::from_raw_parts(start, self.size - size_of::<Tag>());
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        str::from_utf8(&bytes[..end]).unwrap_or("")
+    }
+}
+
 impl Iterator for MultibootInfoIntoIter {
     type Item = *const Tag;
     fn next(&mut self) -> Option<Self::Item> {
         let tag = unsafe { &*(self.inner) };
-        if tag.typ == 0 && tag.size == 8 {
+        if tag.typ == TAG_END && tag.size == 8 {
             None
         } else {
             let offset = match tag.size {
@@ -58,26 +111,140 @@ impl IntoIterator for MultibootInfo {
     }
 }
 
-impl MultibootInfo {
-    pub fn get_memory_map(&self) -> Option<MemoryMap> {
-        for tag in self.into_iter() {
-            if unsafe { (*tag).typ } == 6 {
-                return Some(MemoryMap {
-                    inner: tag as *const MemoryMapTag,
-                });
-            }
+/// Fixed size entries following a tag header
+#[derive(Debug, Copy, Clone)]
+pub struct Entries<T: 'static> {
+    current: usize,
+    end: usize,
+    entry_size: usize,
+    entry: PhantomData<&'static T>,
+}
+
+impl<T> Entries<T> {
+    /// # Safety
+    ///
+    /// `header` must be followed by entries of `entry_size` bytes, up to
+    /// `tag_size` bytes from its start.
+    unsafe fn new<H>(header: &'static H, tag_size: usize, entry_size: usize) -> Entries<T> {
+        let start = header as *const H as usize;
+        Entries {
+            current: start + size_of::<H>(),
+            // An entry smaller than announced would be read past its end
+            end: match entry_size < size_of::<T>() {
+                true => start,
+                false => start + tag_size,
+            },
+            entry_size,
+            entry: PhantomData,
         }
+    }
+}
 
-        None
+impl<T> Iterator for Entries<T> {
+    type Item = &'static T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.current + self.entry_size > self.end {
+            None
+        } else {
+            let ret = unsafe { &*(self.current as *const T) };
+            self.current += self.entry_size;
+            Some(ret)
+        }
+    }
+}
+
+impl MultibootInfo {
+    /// Size of the whole boot information, tags included
+    pub fn total_size(&self) -> usize {
+        unsafe { (*self.inner).total_size as usize }
+    }
+
+    fn find_tag(&self, typ: usize) -> Option<*const Tag> {
+        self.into_iter().find(|&tag| unsafe { (*tag).typ } == typ)
+    }
+
+    /// The tag of type `typ`, as a `T`
+    fn get_tag<T>(&self, typ: usize) -> Option<&'static T> {
+        self.find_tag(typ).map(|tag| unsafe { &*(tag as *const T) })
+    }
+
+    pub fn get_command_line(&self) -> Option<&'static str> {
+        self.find_tag(TAG_COMMAND_LINE)
+            .map(|tag| unsafe { (*tag).string() })
+    }
+
+    pub fn get_bootloader_name(&self) -> Option<&'static str> {
+        self.find_tag(TAG_BOOTLOADER_NAME)
+            .map(|tag| unsafe { (*tag).string() })
+    }
+
+    /// Files loaded with `module2` lines
+    pub fn get_modules(&self) -> impl Iterator<Item = &'static ModuleTag> {
+        self.into_iter()
+            .filter(|&tag| unsafe { (*tag).typ } == TAG_MODULE)
+            .map(|tag| unsafe { &*(tag as *const ModuleTag) })
+    }
+
+    pub fn get_basic_memory_info(&self) -> Option<BasicMemoryInfoTag> {
+        self.get_tag(TAG_BASIC_MEMORY_INFO).copied()
+    }
+
+    pub fn get_bios_boot_device(&self) -> Option<BiosBootDeviceTag> {
+        self.get_tag(TAG_BIOS_BOOT_DEVICE).copied()
+    }
+
+    pub fn get_memory_map(&self) -> Option<&'static MemoryMapTag> {
+        self.get_tag(TAG_MEMORY_MAP)
+    }
+
+    pub fn get_vbe_info(&self) -> Option<&'static VbeInfoTag> {
+        self.get_tag(TAG_VBE_INFO)
     }
 
     pub fn get_framebuffer(&self) -> Option<FramebufferTag> {
-        for tag in self.into_iter() {
-            if unsafe { (*tag).typ } == 8 {
-                return Some(unsafe { *(tag as *const FramebufferTag) });
-            }
-        }
+        self.get_tag(TAG_FRAMEBUFFER).copied()
+    }
 
-        None
+    pub fn get_elf_sections(&self) -> Option<&'static ElfSectionsTag> {
+        self.get_tag(TAG_ELF_SECTIONS)
     }
+
+    pub fn get_apm_table(&self) -> Option<ApmTableTag> {
+        self.get_tag(TAG_APM_TABLE).copied()
+    }
+
+    /// ACPI 1.0 RSDP, copied by the boot loader
+    pub fn get_acpi_old_rsdp(&self) -> Option<&'static RsdpV1> {
+        self.find_tag(TAG_ACPI_OLD_RSDP)
+            .map(|tag| unsafe { &*(tag.offset(1) as *const RsdpV1) })
+    }
+
+    /// ACPI 2.0 RSDP, copied by the boot loader
+    pub fn get_acpi_new_rsdp(&self) -> Option<&'static RsdpV2> {
+        self.find_tag(TAG_ACPI_NEW_RSDP)
+            .map(|tag| unsafe { &*(tag.offset(1) as *const RsdpV2) })
+    }
+
+    pub fn get_efi_memory_map(&self) -> Option<&'static EfiMemoryMapTag> {
+        self.get_tag(TAG_EFI_MEMORY_MAP)
+    }
+
+    /// Physical address the kernel image was loaded at
+    pub fn get_load_base_address(&self) -> Option<usize> {
+        self.find_tag(TAG_LOAD_BASE_ADDRESS)
+            .map(|tag| unsafe { *(tag.offset(1) as *const u32) as usize })
+    }
+}
+
+/// Boot information saved for later inspection
+static BOOT_INFO: Mutex<Option<MultibootInfo>> = Mutex::new(None);
+
+/// Keep the boot information, which must stay mapped
+pub fn save(info: MultibootInfo) {
+    BOOT_INFO.lock().replace(info);
+}
+
+pub fn boot_info() -> Option<MultibootInfo> {
+    *BOOT_INFO.lock()
 }
@@ -0,0 +1,29 @@
+use core::slice;
+use core::str;
+
+/// A file loaded by the boot loader along the kernel
+#[repr(C)]
+#[derive(Debug)]
+pub struct ModuleTag {
+    typ: usize,
+    size: usize,
+    /// Physical address of the first byte
+    pub mod_start: u32,
+    /// Physical address following the last byte
+    pub mod_end: u32,
+}
+
+impl ModuleTag {
+    /// The command line given to the module, usually its name
+    pub fn name(&self) -> &str {
+        let start = unsafe { (self as *const ModuleTag).offset(1) as *const u8 };
+        let len = self.size - core::mem::size_of::<ModuleTag>();
+        let bytes = unsafe { slice::from_raw_parts(start, len) };
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(len);
+        str::from_utf8(&bytes[..end]).unwrap_or("")
+    }
+
+    pub fn len(&self) -> usize {
+        (self.mod_end - self.mod_start) as usize
+    }
+}
@@ -0,0 +1,141 @@
+use super::Entries;
+
+/// Amount of lower and upper memory, in KiB
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct BasicMemoryInfoTag {
+    typ: usize,
+    size: usize,
+    /// Memory below 1 MiB
+    pub mem_lower: u32,
+    /// Memory above 1 MiB, up to the first hole
+    pub mem_upper: u32,
+}
+
+/// BIOS disk the kernel was loaded from
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct BiosBootDeviceTag {
+    typ: usize,
+    size: usize,
+    pub biosdev: u32,
+    pub partition: u32,
+    pub sub_partition: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct VbeInfoTag {
+    typ: usize,
+    size: usize,
+    pub vbe_mode: u16,
+    pub vbe_interface_seg: u16,
+    pub vbe_interface_off: u16,
+    pub vbe_interface_len: u16,
+    pub vbe_control_info: [u8; 512],
+    pub vbe_mode_info: [u8; 256],
+}
+
+/// Sections of the kernel image
+///
+/// GRUB stores the counts on 32 bits, unlike the 16 bits of the
+/// specification.
+#[repr(C)]
+#[derive(Debug)]
+pub struct ElfSectionsTag {
+    typ: usize,
+    size: usize,
+    pub num: u32,
+    entsize: u32,
+    /// Index of the section names string table
+    pub shndx: u32,
+}
+
+/// ELF32 section header
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ElfSection {
+    /// Offset of the name in the string table section
+    pub name: u32,
+    pub typ: u32,
+    pub flags: u32,
+    pub addr: u32,
+    pub offset: u32,
+    pub size: u32,
+    pub link: u32,
+    pub info: u32,
+    pub addralign: u32,
+    pub entsize: u32,
+}
+
+impl ElfSectionsTag {
+    pub fn sections(&'static self) -> Entries<ElfSection> {
+        unsafe { Entries::new(self, self.size, self.entsize as usize) }
+    }
+}
+
+/// Advanced Power Management BIOS interface
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ApmTableTag {
+    typ: usize,
+    size: usize,
+    pub version: u16,
+    pub cseg: u16,
+    pub offset: u32,
+    pub cseg_16: u16,
+    pub dseg: u16,
+    pub flags: u16,
+    pub cseg_len: u16,
+    pub cseg_16_len: u16,
+    pub dseg_len: u16,
+}
+
+/// ACPI 1.0 Root System Description Pointer
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct RsdpV1 {
+    pub signature: [u8; 8],
+    pub checksum: u8,
+    pub oem_id: [u8; 6],
+    pub revision: u8,
+    pub rsdt_address: u32,
+}
+
+/// ACPI 2.0 Root System Description Pointer
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct RsdpV2 {
+    pub v1: RsdpV1,
+    pub length: u32,
+    pub xsdt_address: u64,
+    pub extended_checksum: u8,
+    _reserved: [u8; 3],
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct EfiMemoryMapTag {
+    typ: usize,
+    size: usize,
+    descriptor_size: u32,
+    pub descriptor_version: u32,
+}
+
+/// UEFI memory descriptor
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct EfiMemoryDescriptor {
+    pub typ: u32,
+    _padding: u32,
+    pub physical_start: u64,
+    pub virtual_start: u64,
+    pub number_of_pages: u64,
+    pub attribute: u64,
+}
+
+impl EfiMemoryMapTag {
+    pub fn descriptors(&'static self) -> Entries<EfiMemoryDescriptor> {
+        unsafe { Entries::new(self, self.size, self.descriptor_size as usize) }
+    }
+}
@@ -0,0 +1,275 @@
+//! Buddy allocator
+//!
+//! Free frames are grouped in blocks of 2^order contiguous frames, aligned on
+//! their size. A block is split in two buddies to serve a smaller order, and
+//! merged back with its buddy once both are free.
+//!
+//! The free blocks of each order are kept in a bitmap, one bit per block, with
+//! a summary level of the words still holding a free block.
+//!
+//! Blocks never span two zones, they are searched zone by zone.
+
+use core::cmp::Ordering;
+use core::fmt;
+use core::ops::Range;
+use core::slice;
+
+use super::{Zone, PAGE_SIZE_4K};
+
+/// Low, DMA and normal
+const N_ZONES: usize = 3;
+
+/// Largest order, blocks of 4 MiB
+pub const MAX_ORDER: usize = 10;
+
+/// Lengths of the summary and of the bitmap, in words, for `bits` bits
+pub(super) fn words(bits: usize) -> (usize, usize) {
+    let bitmap = (bits + 31) / 32;
+    ((bitmap + 31) / 32, bitmap)
+}
+
+/// One bit per block of an order, set if the block is free
+struct BlockBitmap {
+    summary: &'static mut [u32],
+    bitmap: &'static mut [u32],
+    /// No free block of each zone in the summary words below
+    skip: [usize; N_ZONES],
+}
+
+impl BlockBitmap {
+    /// Bits at `storage`, return the address following them
+    unsafe fn at(storage: usize, blocks: usize) -> (BlockBitmap, usize) {
+        let (summary, bitmap) = words(blocks);
+        let blocks = BlockBitmap {
+            summary: slice::from_raw_parts_mut(storage as *mut u32, summary),
+            bitmap: slice::from_raw_parts_mut((storage + 4 * summary) as *mut u32, bitmap),
+            skip: [0; N_ZONES],
+        };
+        (blocks, storage + 4 * (summary + bitmap))
+    }
+
+    fn clear_all(&mut self) {
+        for word in self.summary.iter_mut().chain(self.bitmap.iter_mut()) {
+            *word = 0;
+        }
+        self.skip = [0; N_ZONES];
+    }
+
+    fn is_free(&self, block: usize) -> bool {
+        block / 32 < self.bitmap.len() && self.bitmap[block / 32] & (0x80000000 >> block % 32) != 0
+    }
+
+    /// Mark `block`, of `zone`, free or not
+    fn set(&mut self, block: usize, zone: Zone, free: bool) {
+        let index = block / 32;
+        match free {
+            true => self.bitmap[index] |= 0x80000000 >> block % 32,
+            false => self.bitmap[index] &= !(0x80000000 >> block % 32),
+        }
+        let bit = 0x80000000 >> index % 32;
+        match self.bitmap[index] == 0 {
+            true => self.summary[index / 32] &= !bit,
+            false => self.summary[index / 32] |= bit,
+        }
+        if free {
+            let skip = &mut self.skip[zone as usize];
+            *skip = (*skip).min(index / 32);
+        }
+    }
+
+    /// First free block of `blocks`, all of `zone`
+    fn first_in(&mut self, blocks: Range<usize>, zone: Zone) -> Option<usize> {
+        if blocks.start >= blocks.end {
+            return None;
+        }
+        let (summary_words, bitmap_words) = words(blocks.end);
+        let skip = self.skip[zone as usize].max(blocks.start / 1024);
+        // First summary word holding a free block, of any zone
+        let mut lowest = summary_words;
+        for i in skip..summary_words {
+            let mut summary = self.summary[i];
+            if summary != 0 {
+                lowest = lowest.min(i);
+            }
+            while summary != 0 {
+                let word = i * 32 + summary.leading_zeros() as usize;
+                summary &= !(0x80000000 >> word % 32);
+                if word >= bitmap_words {
+                    break;
+                }
+                let bits = match word.cmp(&(blocks.start / 32)) {
+                    Ordering::Less => continue,
+                    Ordering::Equal => self.bitmap[word] & !0 >> blocks.start % 32,
+                    Ordering::Greater => self.bitmap[word],
+                };
+                if bits != 0 {
+                    self.skip[zone as usize] = lowest;
+                    let block = word * 32 + bits.leading_zeros() as usize;
+                    return Some(block).filter(|block| blocks.contains(block));
+                }
+            }
+        }
+        self.skip[zone as usize] = lowest;
+        None
+    }
+}
+
+/// Free blocks of every order
+pub struct BuddyAllocator {
+    orders: [BlockBitmap; MAX_ORDER + 1],
+    /// Number of free blocks of each order, in each zone
+    counts: [[usize; MAX_ORDER + 1]; N_ZONES],
+}
+
+impl BuddyAllocator {
+    pub(super) const fn empty() -> BuddyAllocator {
+        const EMPTY: BlockBitmap = BlockBitmap {
+            summary: &mut [],
+            bitmap: &mut [],
+            skip: [0; N_ZONES],
+        };
+        BuddyAllocator {
+            orders: [EMPTY; MAX_ORDER + 1],
+            counts: [[0; MAX_ORDER + 1]; N_ZONES],
+        }
+    }
+
+    /// Bytes of storage needed for `frames` frames
+    pub(super) fn storage_size(frames: usize) -> usize {
+        (0..=MAX_ORDER)
+            .map(|order| {
+                let (summary, bitmap) = words(blocks(frames, order));
+                4 * (summary + bitmap)
+            })
+            .sum()
+    }
+
+    /// Point the bitmaps to `storage`, for `frames` frames
+    pub(super) unsafe fn set_storage(&mut self, mut storage: usize, frames: usize) {
+        for (order, blocks_bitmap) in self.orders.iter_mut().enumerate() {
+            let skip = blocks_bitmap.skip;
+            let (bitmap, next) = BlockBitmap::at(storage, blocks(frames, order));
+            *blocks_bitmap = bitmap;
+            blocks_bitmap.skip = skip;
+            storage = next;
+        }
+    }
+
+    /// Forget every free block
+    pub(super) fn clear(&mut self) {
+        for blocks_bitmap in self.orders.iter_mut() {
+            blocks_bitmap.clear_all();
+        }
+        self.counts = [[0; MAX_ORDER + 1]; N_ZONES];
+    }
+
+    fn set_free(&mut self, frame: usize, order: usize, free: bool) {
+        let zone = Zone::of(frame);
+        self.orders[order].set(frame >> order, zone, free);
+        let count = &mut self.counts[zone as usize][order];
+        match free {
+            true => *count += 1,
+            false => *count -= 1,
+        }
+    }
+
+    /// Take a block of `order` from `zone`, lying below the frame number
+    /// `end`
+    ///
+    /// A bigger block is split if needed, its upper halves stay free. Return
+    /// the first frame number of the block.
+    pub(super) fn alloc(&mut self, order: usize, zone: Zone, end: usize) -> Option<usize> {
+        let frames = zone.frames();
+        let end = end.min(frames.end);
+        if end < frames.start + (1 << order) {
+            return None;
+        }
+        // Blocks of each order starting low enough
+        let last_frame = end - (1 << order);
+        let (found, frame) = (order..=MAX_ORDER).find_map(|found| {
+            let first = (frames.start + (1 << found) - 1) >> found;
+            self.orders[found]
+                .first_in(first..(last_frame >> found) + 1, zone)
+                .map(|block| (found, block << found))
+        })?;
+        self.set_free(frame, found, false);
+        for half in (order..found).rev() {
+            self.set_free(frame + (1 << half), half, true);
+        }
+        Some(frame)
+    }
+
+    /// Give back a block of `order`, merged with its free buddies of the same
+    /// zone
+    pub(super) fn free(&mut self, mut frame: usize, mut order: usize) {
+        while order < MAX_ORDER && self.orders[order].is_free((frame >> order) ^ 1) {
+            let merged = frame & !(1 << order);
+            if Zone::of(merged) != Zone::of(merged + (2 << order) - 1) {
+                break;
+            }
+            self.set_free(frame ^ (1 << order), order, false);
+            frame = merged;
+            order += 1;
+        }
+        self.set_free(frame, order, true);
+    }
+
+    /// Take the frame number `frame` out of its free block
+    ///
+    /// The rest of the block stays free, split around the frame. Return false
+    /// if the frame is not free.
+    pub(super) fn remove(&mut self, frame: usize) -> bool {
+        let order = match (0..=MAX_ORDER).find(|&order| self.orders[order].is_free(frame >> order))
+        {
+            Some(order) => order,
+            None => return false,
+        };
+        let mut start = (frame >> order) << order;
+        self.set_free(start, order, false);
+        for half in (0..order).rev() {
+            match frame >= start + (1 << half) {
+                true => {
+                    self.set_free(start, half, true);
+                    start += 1 << half;
+                }
+                false => self.set_free(start + (1 << half), half, true),
+            }
+        }
+        true
+    }
+
+    /// Number of free blocks of `order` in `zone`
+    pub fn free_blocks(&self, zone: Zone, order: usize) -> usize {
+        self.counts[zone as usize][order]
+    }
+}
+
+/// Blocks of `order` needed to cover `frames` frames
+fn blocks(frames: usize, order: usize) -> usize {
+    (frames + (1 << order) - 1) >> order
+}
+
+impl fmt::Display for BuddyAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "order  block size")?;
+        for zone in Zone::ALL.iter() {
+            write!(f, "  {:>8}", zone)?;
+        }
+        writeln!(f)?;
+        for order in 0..=MAX_ORDER {
+            write!(f, "{:>5}  {:>6} KiB", order, (PAGE_SIZE_4K << order) / 1024)?;
+            for &zone in Zone::ALL.iter() {
+                write!(f, "  {:>8}", self.free_blocks(zone, order))?;
+            }
+            writeln!(f)?;
+        }
+        write!(f, "free frames      ")?;
+        for &zone in Zone::ALL.iter() {
+            let frames: usize = (0..=MAX_ORDER)
+                .map(|order| self.free_blocks(zone, order) << order)
+                .sum();
+            write!(f, "  {:>8}", frames)?;
+        }
+        Ok(())
+    }
+}
@@ -2,20 +2,48 @@
 //!
 //! Keep track of the availibility of each physical page frame.
 //! Optimize time complexity of finding an available one.
+//! Count the references to frames shared between address spaces.
+//!
+//! Physical addresses are 64 bit wide, PAE paging reaches frames above 4 GiB.
+//!
+//! The tracking structures are sized from the installed RAM, and stored in
+//! frames taken from it at boot.
+//!
+//! Free frames are handed out by a buddy allocator, in blocks of contiguous
+//! frames, from the highest memory zone allowed.
+
+mod buddy;
+mod zone;
+
+pub use self::buddy::{BuddyAllocator, MAX_ORDER};
+pub use self::zone::Zone;
+use core::slice;
 
 /// 4096
 pub const PAGE_SIZE_4K: usize = 4096;
-const N_FRAMES: usize = 0x100000;
-const BITMAP_LEN: usize = N_FRAMES / 32;
+/// Frames of the first 16 GiB, at most
+const MAX_FRAMES: usize = 0x400000;
+
+/// Address of a byte of physical memory
+pub type PhysicalAddress = u64;
 
 /// Bitmap representation of physical memrory
 ///
 /// One bit for each page frame:
 /// - Set => in use
 /// - Clear => available
+///
+/// The available frames are also kept as free blocks by the buddy allocator.
+///
+/// At boot every frame is marked in use, then the RAM reported by the boot
+/// loader is released.
 pub struct FrameManager {
-    bitmap: [u32; BITMAP_LEN],
-    skip: usize,
+    bitmap: &'static mut [u32],
+    buddy: BuddyAllocator,
+    /// Number of frames that can be handed out, from address 0
+    limit: usize,
+    /// References to each frame in use, beside the first one
+    references: &'static mut [u8],
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -24,13 +52,17 @@ pub enum PhysicalMemoryError {
     FrameAlreadyInUse,
     FrameNotInUse,
     AddressOutOfMemory,
+    /// A frame is shared too many times to be counted
+    TooManyReferences,
+    /// Blocks are at most of `MAX_ORDER`
+    OrderTooLarge,
 }
 
 #[derive(Copy, Clone)]
-struct PageFrame(usize);
+struct PageFrame(PhysicalAddress);
 
 impl PageFrame {
-    fn new(address: usize) -> PageFrame {
+    fn new(address: PhysicalAddress) -> PageFrame {
         assert_eq!(
             0,
             address & 0xFFF,
@@ -40,44 +72,97 @@ impl PageFrame {
         PageFrame(address)
     }
 
+    fn from_number(number: usize) -> PageFrame {
+        PageFrame(number as PhysicalAddress * PAGE_SIZE_4K as PhysicalAddress)
+    }
+
     fn index(&self) -> usize {
-        self.0 / PAGE_SIZE_4K / 32
+        self.number() / 32
     }
 
     fn offset(&self) -> usize {
-        self.0 / PAGE_SIZE_4K % 32
+        self.number() % 32
     }
 
-    fn address(&self) -> usize {
+    fn address(&self) -> PhysicalAddress {
         self.0
     }
+
+    /// Frame number, saturated for the frames too far to be tracked
+    fn number(&self) -> usize {
+        let number = self.0 / PAGE_SIZE_4K as PhysicalAddress;
+        match number < MAX_FRAMES as PhysicalAddress {
+            true => number as usize,
+            false => MAX_FRAMES,
+        }
+    }
 }
 
 impl fmt::Display for PageFrame {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:#010x}", self.address())
+        write!(f, "{:#012x}", self.address())
     }
 }
 
 impl FrameManager {
-    fn next_available(&self) -> Result<PageFrame, PhysicalMemoryError> {
-        let idx = self.bitmap.iter().skip(self.skip).position(|&x| x != !0);
-
-        idx.map_or(Err(PhysicalMemoryError::NoFrameAvailable), |i| {
-            let mut j: usize = 0;
-            let real_idx = i + self.skip;
-            while !self.bitmap[real_idx] & (0x80000000 >> j) == 0 {
-                j += 1;
-            }
-            Ok(PageFrame((real_idx * 32 + j) * PAGE_SIZE_4K))
-        })
+    /// Bytes of storage needed to track the frames below `end`, a whole
+    /// number of frames
+    pub fn storage_size(end: PhysicalAddress) -> usize {
+        let frames = PageFrame::new(end & !0xFFF).number();
+        let size = 4 * buddy::words(frames).1 + BuddyAllocator::storage_size(frames) + frames;
+        (size + PAGE_SIZE_4K - 1) & !(PAGE_SIZE_4K - 1)
+    }
+
+    /// Point the tracking structures to `storage`, for `frames` frames
+    unsafe fn set_storage(&mut self, storage: usize, frames: usize) {
+        let bitmap = buddy::words(frames).1;
+        self.bitmap = slice::from_raw_parts_mut(storage as *mut u32, bitmap);
+        let buddy = storage + 4 * bitmap;
+        self.buddy.set_storage(buddy, frames);
+        self.references = slice::from_raw_parts_mut(
+            (buddy + BuddyAllocator::storage_size(frames)) as *mut u8,
+            frames,
+        );
+    }
+
+    /// Track the frames below `end`, in the `storage_size(end)` bytes at the
+    /// virtual address `storage`, and mark them all in use
+    ///
+    /// Done at boot, before the RAM is released. Without PAE, frames above
+    /// 4 GiB can't be mapped.
+    pub unsafe fn reset(&mut self, end: PhysicalAddress, storage: usize) {
+        self.limit = PageFrame::new(end & !0xFFF).number();
+        self.set_storage(storage, self.limit);
+        self.buddy.clear();
+        for word in self.bitmap.iter_mut() {
+            *word = !0;
+        }
+        for references in self.references.iter_mut() {
+            *references = 0;
+        }
+    }
+
+    /// Reach the tracking structures at the virtual address `storage`, once
+    /// their frames are mapped there
+    pub unsafe fn relocate(&mut self, storage: usize) {
+        self.set_storage(storage, self.limit);
+    }
+
+    /// End of the physical memory frames are handed out from
+    pub fn limit(&self) -> PhysicalAddress {
+        PageFrame::from_number(self.limit).address()
+    }
+
+    /// Free blocks of contiguous frames
+    pub fn buddy(&self) -> &BuddyAllocator {
+        &self.buddy
     }
 
     fn mark_as_used(&mut self, page: PageFrame) -> Result<(), PhysicalMemoryError> {
         let i = page.index();
         let o = page.offset();
 
-        if i >= BITMAP_LEN {
+        if page.number() >= self.limit {
             return Err(PhysicalMemoryError::AddressOutOfMemory);
         }
 
@@ -85,7 +170,7 @@ impl FrameManager {
             false => Err(PhysicalMemoryError::FrameAlreadyInUse),
             true => {
                 self.bitmap[i] |= 0x80000000 >> o;
-                self.skip = i;
+                self.buddy.remove(page.number());
                 Ok(())
             }
         }
@@ -95,34 +180,182 @@ impl FrameManager {
         let i = page.index();
         let o = page.offset();
 
+        if page.number() >= self.limit {
+            return Err(PhysicalMemoryError::AddressOutOfMemory);
+        }
+
         match self.bitmap[i] & (0x80000000 >> o) != 0 {
             false => Err(PhysicalMemoryError::FrameNotInUse),
             true => {
                 self.bitmap[i] &= !(0x80000000 >> o);
-                self.skip = i;
+                self.buddy.free(page.number(), 0);
                 Ok(())
             }
         }
     }
 
-    pub fn alloc_frame(&mut self) -> Result<usize, PhysicalMemoryError> {
-        let p = self.next_available()?;
-        self.mark_as_used(p)?;
-        Ok(p.address())
+    /// Take a block from the buddy allocator, from `zone` or the zones it
+    /// falls back to, below the frame number `end`, and mark its frames in use
+    fn take_block(
+        &mut self,
+        order: usize,
+        zone: Zone,
+        end: usize,
+    ) -> Result<PhysicalAddress, PhysicalMemoryError> {
+        if order > MAX_ORDER {
+            return Err(PhysicalMemoryError::OrderTooLarge);
+        }
+        let first = zone
+            .fallback()
+            .iter()
+            .find_map(|&zone| self.buddy.alloc(order, zone, end))
+            .ok_or(PhysicalMemoryError::NoFrameAvailable)?;
+        for number in first..first + (1 << order) {
+            let page = PageFrame::from_number(number);
+            self.bitmap[page.index()] |= 0x80000000 >> page.offset();
+        }
+        Ok(PageFrame::from_number(first).address())
     }
 
-    pub fn alloc_frame_by_address(&mut self, address: usize) -> Result<(), PhysicalMemoryError> {
+    /// Allocate a frame, preferably above the DMA zone
+    pub fn alloc_frame(&mut self) -> Result<PhysicalAddress, PhysicalMemoryError> {
+        self.take_block(0, Zone::Normal, self.limit)
+    }
+
+    /// Allocate a frame in `zone`, or a lower one
+    pub fn alloc_frame_in(&mut self, zone: Zone) -> Result<PhysicalAddress, PhysicalMemoryError> {
+        self.take_block(0, zone, self.limit)
+    }
+
+    /// Allocate a frame below `end`, for the structures only reached through
+    /// 32 bit physical addresses
+    pub fn alloc_frame_below(
+        &mut self,
+        end: PhysicalAddress,
+    ) -> Result<PhysicalAddress, PhysicalMemoryError> {
+        let end = PageFrame::new(end & !0xFFF).number().min(self.limit);
+        self.take_block(0, Zone::Normal, end)
+    }
+
+    /// Allocate 2^`order` contiguous frames, aligned on their size
+    pub fn alloc_frames(&mut self, order: usize) -> Result<PhysicalAddress, PhysicalMemoryError> {
+        self.take_block(order, Zone::Normal, self.limit)
+    }
+
+    /// Allocate 2^`order` contiguous frames in `zone`, or a lower one
+    pub fn alloc_frames_in(
+        &mut self,
+        zone: Zone,
+        order: usize,
+    ) -> Result<PhysicalAddress, PhysicalMemoryError> {
+        self.take_block(order, zone, self.limit)
+    }
+
+    /// Release the 2^`order` frames allocated by `alloc_frames`
+    ///
+    /// They must not be shared.
+    pub fn free_frames(
+        &mut self,
+        address: PhysicalAddress,
+        order: usize,
+    ) -> Result<(), PhysicalMemoryError> {
+        if order > MAX_ORDER {
+            return Err(PhysicalMemoryError::OrderTooLarge);
+        }
+        let first = self.tracked(address)?.number();
+        assert_eq!(
+            0,
+            first & ((1 << order) - 1),
+            "block is not aligned on its size: {:#010x}",
+            address
+        );
+        let frames = first..first + (1 << order);
+        if frames.end > self.limit {
+            return Err(PhysicalMemoryError::AddressOutOfMemory);
+        }
+        if frames
+            .clone()
+            .any(|number| self.is_available(PageFrame::from_number(number).address()))
+        {
+            return Err(PhysicalMemoryError::FrameNotInUse);
+        }
+        for number in frames {
+            let page = PageFrame::from_number(number);
+            self.bitmap[page.index()] &= !(0x80000000 >> page.offset());
+        }
+        self.buddy.free(first, order);
+        Ok(())
+    }
+
+    pub fn alloc_frame_by_address(
+        &mut self,
+        address: PhysicalAddress,
+    ) -> Result<(), PhysicalMemoryError> {
         self.mark_as_used(PageFrame::new(address))
     }
 
-    pub fn free_frame(&mut self, address: usize) -> Result<(), PhysicalMemoryError> {
-        self.mark_as_available(PageFrame::new(address))
+    /// Make available the frames fully inside `start..end`
+    ///
+    /// Used at boot, for the RAM reported by the boot loader. Frames beyond
+    /// the limit are ignored.
+    pub fn release_range(&mut self, start: PhysicalAddress, end: PhysicalAddress) {
+        let first = PageFrame::new((start + 0xFFF) & !0xFFF).number();
+        let last = PageFrame::new(end & !0xFFF).number().min(self.limit);
+        for number in first..last {
+            self.mark_as_available(PageFrame::from_number(number)).ok();
+        }
+    }
+
+    /// Release a frame
+    ///
+    /// A shared frame is only made available once its last reference is
+    /// released.
+    pub fn free_frame(&mut self, address: PhysicalAddress) -> Result<(), PhysicalMemoryError> {
+        let page = self.tracked(address)?;
+        if self.references[page.number()] > 0 && !self.is_available(address) {
+            self.references[page.number()] -= 1;
+            return Ok(());
+        }
+        self.mark_as_available(page)
+    }
+
+    /// Add a reference to a frame in use
+    ///
+    /// It must then be freed once more.
+    pub fn share_frame(&mut self, address: PhysicalAddress) -> Result<(), PhysicalMemoryError> {
+        let page = self.tracked(address)?;
+        if self.is_available(address) {
+            return Err(PhysicalMemoryError::FrameNotInUse);
+        }
+        let references = &mut self.references[page.number()];
+        *references = references
+            .checked_add(1)
+            .ok_or(PhysicalMemoryError::TooManyReferences)?;
+        Ok(())
+    }
+
+    /// Number of references to a frame, 0 if available
+    pub fn reference_count(&self, address: PhysicalAddress) -> usize {
+        match self.tracked(address) {
+            Ok(page) if !self.is_available(address) => 1 + self.references[page.number()] as usize,
+            _ => 0,
+        }
+    }
+
+    /// The frame at `address`, if below the limit
+    fn tracked(&self, address: PhysicalAddress) -> Result<PageFrame, PhysicalMemoryError> {
+        let page = PageFrame::new(address);
+        match page.number() < self.limit {
+            true => Ok(page),
+            false => Err(PhysicalMemoryError::AddressOutOfMemory),
+        }
     }
 
-    pub fn is_available(&self, address: usize) -> bool {
+    /// Frames beyond the limit are never available
+    pub fn is_available(&self, address: PhysicalAddress) -> bool {
         let page = PageFrame::new(address);
 
-        self.bitmap[page.index()] & (0x80000000 >> page.offset()) == 0
+        page.number() < self.limit && self.bitmap[page.index()] & (0x80000000 >> page.offset()) == 0
     }
 }
 
@@ -132,9 +365,9 @@ impl fmt::Display for FrameManager {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "Used frames:")?;
         for (i, u) in self.bitmap.iter().enumerate() {
-            for j in 0..32 {
+            for j in (0..32).filter(|j| 32 * i + j < self.limit) {
                 if u & (0x80000000 >> j) != 0 {
-                    write!(f, "{:#010x} ", PAGE_SIZE_4K * (32 * i + j))?;
+                    write!(f, "{} ", PageFrame::from_number(32 * i + j))?;
                 }
             }
         }
@@ -142,10 +375,25 @@ impl fmt::Display for FrameManager {
     }
 }
 
+use crate::idt;
 use spin::Mutex;
 
 /// Unique source of true for physical memory management
-pub static BITMAP: Mutex<FrameManager> = Mutex::new(FrameManager {
-    bitmap: [0; BITMAP_LEN],
-    skip: 0,
+///
+/// Empty until `reset` gives it its storage.
+static BITMAP: Mutex<FrameManager> = Mutex::new(FrameManager {
+    bitmap: &mut [],
+    buddy: BuddyAllocator::empty(),
+    limit: 0,
+    references: &mut [],
 });
+
+/// Access the frame manager, with interrupts disabled
+///
+/// Page faults allocate frames, they must not find it locked.
+pub fn frame_manager<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut FrameManager) -> R,
+{
+    idt::without_interrupts(|| f(&mut BITMAP.lock()))
+}
@@ -0,0 +1,77 @@
+//! Physical memory zones
+//!
+//! Some frames are scarce and needed by few users: real mode code only runs
+//! below 1 MiB, ISA DMA only reaches the first 16 MiB. Frames are handed out
+//! from the highest zone allowed, lower zones only serve as a fallback.
+
+use core::fmt;
+use core::ops::Range;
+
+use super::{PhysicalAddress, PAGE_SIZE_4K};
+
+/// First frame number of the DMA zone, at 1 MiB
+const DMA_START: usize = 0x100;
+/// First frame number of the normal zone, at 16 MiB
+const NORMAL_START: usize = 0x1000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Zone {
+    /// Below 1 MiB, for real mode trampolines
+    Low,
+    /// Below 16 MiB, for ISA DMA
+    Dma,
+    /// Everything above
+    Normal,
+}
+
+impl Zone {
+    pub const ALL: [Zone; 3] = [Zone::Low, Zone::Dma, Zone::Normal];
+
+    /// Zone of the frame number `frame`
+    pub fn of(frame: usize) -> Zone {
+        match frame {
+            f if f < DMA_START => Zone::Low,
+            f if f < NORMAL_START => Zone::Dma,
+            _ => Zone::Normal,
+        }
+    }
+
+    /// Frame numbers of the zone
+    pub(super) fn frames(self) -> Range<usize> {
+        match self {
+            Zone::Low => 0..DMA_START,
+            Zone::Dma => DMA_START..NORMAL_START,
+            Zone::Normal => NORMAL_START..usize::MAX,
+        }
+    }
+
+    /// Physical addresses of the zone
+    pub fn range(self) -> Range<PhysicalAddress> {
+        let frames = self.frames();
+        let end = match self {
+            Zone::Normal => PhysicalAddress::MAX,
+            _ => (frames.end * PAGE_SIZE_4K) as PhysicalAddress,
+        };
+        (frames.start * PAGE_SIZE_4K) as PhysicalAddress..end
+    }
+
+    /// Zones to allocate from, in order, for an allocation in this one
+    pub(super) fn fallback(self) -> &'static [Zone] {
+        match self {
+            Zone::Low => &[Zone::Low],
+            Zone::Dma => &[Zone::Dma, Zone::Low],
+            Zone::Normal => &[Zone::Normal, Zone::Dma, Zone::Low],
+        }
+    }
+}
+
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Zone::Low => "low",
+            Zone::Dma => "dma",
+            Zone::Normal => "normal",
+        };
+        f.pad(name)
+    }
+}
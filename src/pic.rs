@@ -0,0 +1,177 @@
+//! 8259 Programmable Interrupt Controller driver
+//!
+//! # Features
+//! - Remap both PICs above the CPU exceptions vectors
+//! - Mask and unmask IRQ lines
+//! - End of interrupt and spurious IRQs detection
+
+use crate::idt;
+use crate::io_port::{self, Port};
+
+/// First vector used by the master PIC
+pub const MASTER_OFFSET: u8 = 0x20;
+/// First vector used by the slave PIC
+pub const SLAVE_OFFSET: u8 = 0x28;
+
+const ICW1_ICW4: u8 = 0x01;
+const ICW1_INIT: u8 = 0x10;
+const ICW4_8086: u8 = 0x01;
+const CMD_END_OF_INTERRUPT: u8 = 0x20;
+const CMD_READ_ISR: u8 = 0x0B;
+
+/// Cascade line of the master PIC, where the slave is plugged
+const CASCADE_IRQ: u8 = 2;
+
+/// A single 8259 chip
+struct Pic {
+    command: Port<u8>,
+    data: Port<u8>,
+}
+
+impl Pic {
+    fn in_service(&self) -> u8 {
+        self.command.write(CMD_READ_ISR);
+        self.command.read()
+    }
+
+    fn end_of_interrupt(&self) {
+        self.command.write(CMD_END_OF_INTERRUPT);
+    }
+}
+
+/// The master and slave PICs, wired as on the IBM PC/AT
+pub struct ChainedPics {
+    master: Pic,
+    slave: Pic,
+}
+
+impl ChainedPics {
+    fn remap(&self) {
+        // ICW1: start the initialization sequence in cascade mode
+        self.master.command.write(ICW1_INIT | ICW1_ICW4);
+        io_port::wait();
+        self.slave.command.write(ICW1_INIT | ICW1_ICW4);
+        io_port::wait();
+
+        // ICW2: vector offsets
+        self.master.data.write(MASTER_OFFSET);
+        io_port::wait();
+        self.slave.data.write(SLAVE_OFFSET);
+        io_port::wait();
+
+        // ICW3: master/slave wiring
+        self.master.data.write(1 << CASCADE_IRQ);
+        io_port::wait();
+        self.slave.data.write(CASCADE_IRQ);
+        io_port::wait();
+
+        // ICW4: 8086 mode
+        self.master.data.write(ICW4_8086);
+        io_port::wait();
+        self.slave.data.write(ICW4_8086);
+        io_port::wait();
+    }
+
+    fn pic_of(&self, irq: u8) -> (&Pic, u8) {
+        assert!(irq < 16, "invalid IRQ line: {}", irq);
+        match irq {
+            irq if irq < 8 => (&self.master, irq),
+            irq => (&self.slave, irq - 8),
+        }
+    }
+
+    fn set_mask(&self, irq: u8, masked: bool) {
+        let (pic, line) = self.pic_of(irq);
+        idt::without_interrupts(|| {
+            let mask = pic.data.read();
+            pic.data.write(match masked {
+                true => mask | 1 << line,
+                false => mask & !(1 << line),
+            });
+        });
+    }
+}
+
+/// The PICs used by our kernel
+///
+/// Ports access are atomic, so they can be shared with interrupt handlers
+/// without any lock.
+pub static PICS: ChainedPics = ChainedPics {
+    master: Pic {
+        command: Port::new(0x20),
+        data: Port::new(0x21),
+    },
+    slave: Pic {
+        command: Port::new(0xA0),
+        data: Port::new(0xA1),
+    },
+};
+
+/// Initialize the PICs
+///
+/// Remap IRQs 0-15 to the vectors 0x20-0x2F and mask all of them, except
+/// the cascade line. Drivers have to unmask the IRQs they handle.
+pub fn init() {
+    PICS.remap();
+    PICS.master.data.write(!(1 << CASCADE_IRQ));
+    PICS.slave.data.write(0xFF);
+}
+
+/// Allow an IRQ line to raise interrupts
+pub fn unmask(irq: u8) {
+    PICS.set_mask(irq, false);
+}
+
+/// Prevent an IRQ line from raising interrupts
+pub fn mask(irq: u8) {
+    PICS.set_mask(irq, true);
+}
+
+/// Vector raised by an IRQ line
+pub fn vector(irq: u8) -> usize {
+    match irq {
+        irq if irq < 8 => (MASTER_OFFSET + irq) as usize,
+        irq => (SLAVE_OFFSET + irq - 8) as usize,
+    }
+}
+
+/// IRQ line behind an interrupt vector, if any
+pub fn irq_of(vector: usize) -> Option<u8> {
+    match vector {
+        v if v >= MASTER_OFFSET as usize && v < MASTER_OFFSET as usize + 8 => {
+            Some((v - MASTER_OFFSET as usize) as u8)
+        }
+        v if v >= SLAVE_OFFSET as usize && v < SLAVE_OFFSET as usize + 8 => {
+            Some((v - SLAVE_OFFSET as usize + 8) as u8)
+        }
+        _ => None,
+    }
+}
+
+/// Detect spurious IRQs
+///
+/// IRQ 7 and 15 may be raised without any device requesting it. In that case
+/// the in-service register doesn't have the line set, and no end of interrupt
+/// should be sent to the PIC that raised it.
+/// The master still expects one for the cascade line when the slave is the
+/// culprit, it is sent here.
+pub fn is_spurious(irq: u8) -> bool {
+    match irq {
+        7 => PICS.master.in_service() & 1 << 7 == 0,
+        15 if PICS.slave.in_service() & 1 << 7 == 0 => {
+            PICS.master.end_of_interrupt();
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Signal the end of an IRQ handling
+///
+/// Must be sent before the PIC can raise this IRQ again.
+pub fn end_of_interrupt(irq: u8) {
+    if irq >= 8 {
+        PICS.slave.end_of_interrupt();
+    }
+    PICS.master.end_of_interrupt();
+}
@@ -0,0 +1,59 @@
+//! Kernel threads
+//!
+//! Lightweight tasks running a function in ring 0, sharing the kernel address
+//! space. They are scheduled like processes, and let drivers do background
+//! work without blocking the shell.
+
+use super::{exit, table, wait, Pid};
+
+pub use super::scheduler::yield_now;
+
+/// Owned permission to wait for a thread termination
+///
+/// A thread stays a zombie once its function returned, until it is joined.
+/// Dropping the handle detaches the thread, the idle task collects it once
+/// terminated.
+pub struct JoinHandle {
+    pid: Pid,
+}
+
+impl JoinHandle {
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Block until the thread terminates, and return its exit status
+    ///
+    /// The status is 0 when the thread function returned.
+    pub fn join(self) -> usize {
+        wait(self.pid).expect("joined thread does not exist")
+    }
+}
+
+impl Drop for JoinHandle {
+    fn drop(&mut self) {
+        // Already reaped if joined
+        table(|t| {
+            if let Some(thread) = t.get_mut(self.pid) {
+                thread.parent = None;
+            }
+        });
+    }
+}
+
+/// Run `f` in a new kernel thread
+pub fn spawn(name: &str, f: fn()) -> JoinHandle {
+    JoinHandle {
+        pid: table(|t| t.spawn_kernel_task(name, thread_start, f as usize)),
+    }
+}
+
+/// First function run by kernel threads, with the thread function as argument
+extern "C" fn thread_start(f: usize) -> ! {
+    let f: fn() = unsafe { core::mem::transmute(f) };
+    f();
+    exit(0);
+    loop {
+        yield_now();
+    }
+}
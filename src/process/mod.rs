@@ -0,0 +1,534 @@
+//! Processes
+//!
+//! Each process owns an address space: the kernel space is shared by all the
+//! page directories, the user space is private. Kernel tasks only run in the
+//! kernel space, through the kernel page directory.
+//! Processes are kept in a unique table, which also tracks the running one.
+//!
+//! Every process has a parent, the task that created it, which collects its
+//! exit status. Orphans are collected by the idle task.
+
+pub mod kthread;
+pub mod scheduler;
+mod wait_queue;
+
+pub use self::wait_queue::WaitQueue;
+use self::wait_queue::Waiter;
+use crate::dynamic_memory_management::Heap;
+use crate::gdt;
+use crate::idt::{self, InterruptFrame};
+use crate::user_mode::USER_HEAP_START;
+use crate::virtual_memory_management::{self as vmm, VirtualMemoryError};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem::size_of;
+
+/// Process identifier
+pub type Pid = usize;
+
+const KERNEL_STACK_SIZE: usize = 0x4000;
+/// Interrupt flag, and the always set reserved bit
+const INITIAL_EFLAGS: u32 = 0x202;
+/// Exit status of killed processes
+pub const KILLED_STATUS: usize = 128 + 9;
+
+#[derive(Debug)]
+pub enum ProcessError {
+    NoSuchProcess,
+    /// The caller has no child to wait for
+    NoChild,
+    /// Kernel tasks can't be killed, nor forked
+    KernelTask,
+    VirtualMemoryError(VirtualMemoryError),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum State {
+    /// Waiting to be run
+    Ready,
+    /// Currently executed by the CPU
+    Running,
+    /// Waiting for an event
+    Blocked,
+    /// Exited with a status, waiting to be collected
+    Zombie(usize),
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            State::Ready => write!(f, "ready"),
+            State::Running => write!(f, "running"),
+            State::Blocked => write!(f, "blocked"),
+            State::Zombie(status) => write!(f, "zombie ({})", status),
+        }
+    }
+}
+
+/// A task and its resources
+pub struct Process {
+    pub pid: Pid,
+    /// The task which created it, `None` for orphans
+    pub parent: Option<Pid>,
+    pub name: String,
+    pub state: State,
+    /// Timer ticks spent running
+    pub ticks: usize,
+    /// Physical address loaded in CR3: its page directory, or its page
+    /// directory pointer table with PAE
+    page_directory: usize,
+    /// Stack used in kernel mode, `None` for the boot task using the boot stack
+    kernel_stack: Option<Box<[u8]>>,
+    /// Saved registers, on the kernel stack, while not running
+    context: *mut InterruptFrame,
+    /// Taken when the process is dropped, to be freed in its own address space
+    heap: Option<Heap>,
+    /// Terminate the process once it is back in ring 3
+    killed: bool,
+    /// Place in the wait queue it is blocked on
+    waiter: Option<Waiter>,
+}
+
+unsafe impl Send for Process {}
+
+impl Process {
+    fn new(pid: Pid, name: &str, page_directory: usize, heap: Option<Heap>) -> Process {
+        Process {
+            pid,
+            parent: None,
+            name: String::from(name),
+            state: State::Blocked,
+            ticks: 0,
+            page_directory,
+            // Allocated on the heap, the stack is too small to hold it
+            kernel_stack: Some(vec![0u8; KERNEL_STACK_SIZE].into_boxed_slice()),
+            context: core::ptr::null_mut(),
+            heap,
+            killed: false,
+            waiter: None,
+        }
+    }
+
+    /// Physical address of the process page directory
+    pub fn page_directory(&self) -> usize {
+        self.page_directory
+    }
+
+    pub fn is_kernel_task(&self) -> bool {
+        self.page_directory == vmm::kernel_address_space()
+    }
+
+    /// Top of the process kernel stack, `None` for the boot task
+    pub fn kernel_stack_top(&self) -> Option<usize> {
+        self.kernel_stack
+            .as_ref()
+            .map(|stack| stack.as_ptr() as usize + stack.len())
+    }
+
+    /// The process user heap
+    ///
+    /// Only usable while the process address space is the current one.
+    pub fn heap(&mut self) -> Option<&mut Heap> {
+        self.heap.as_mut()
+    }
+
+    /// Make the process ready to start at `frame`
+    ///
+    /// The frame is written on top of its kernel stack, to be restored by the
+    /// scheduler on the first switch to this process.
+    fn start(&mut self, frame: InterruptFrame) {
+        let top = self.kernel_stack_top().unwrap();
+        self.context = (top - size_of::<InterruptFrame>()) as *mut InterruptFrame;
+        unsafe {
+            *self.context = frame;
+        }
+        self.state = State::Ready;
+    }
+
+    /// Make the process ready to start in ring 3
+    pub fn start_user(&mut self, entry: usize, user_stack: usize) {
+        self.start(user_frame(entry, user_stack));
+    }
+
+    /// Make the process ready to start in ring 0, calling `entry(arg)`
+    ///
+    /// Without privilege change, `iret` leaves the stack pointer on the last
+    /// two frame fields: they are used as the return address and argument
+    /// of the call.
+    fn start_kernel(&mut self, entry: extern "C" fn(usize) -> !, arg: usize) {
+        let data = gdt::KERNEL_DATA_SELECTOR as u32;
+        self.start(InterruptFrame {
+            edi: 0,
+            esi: 0,
+            ebp: 0,
+            esp: 0,
+            ebx: 0,
+            edx: 0,
+            ecx: 0,
+            eax: 0,
+            gs: data,
+            fs: data,
+            es: data,
+            ds: data,
+            vector: 0,
+            error_code: 0,
+            eip: entry as usize as u32,
+            cs: gdt::KERNEL_CODE_SELECTOR as u32,
+            eflags: INITIAL_EFLAGS,
+            user_esp: 0,
+            user_ss: arg as u32,
+        });
+    }
+
+    /// Replace the process address space, as well as its name and heap
+    ///
+    /// The previous address space is destroyed. The process must be the
+    /// current one.
+    pub fn replace_image(
+        &mut self,
+        name: &str,
+        page_directory: usize,
+        heap: Heap,
+    ) -> Result<(), VirtualMemoryError> {
+        assert_eq!(
+            self.page_directory,
+            vmm::current_address_space(),
+            "only the current process can replace its image"
+        );
+        let previous = self.page_directory;
+        idt::without_interrupts(|| {
+            // The previous heap is dropped while its address space is loaded
+            self.heap = Some(heap);
+            self.page_directory = page_directory;
+            vmm::switch_address_space(page_directory);
+        });
+        self.name = String::from(name);
+        vmm::destroy_address_space(previous)
+    }
+
+    /// Load the process address space and kernel stack
+    fn load(&self) {
+        if let Some(top) = self.kernel_stack_top() {
+            gdt::set_kernel_stack(top as u32);
+        }
+        if vmm::current_address_space() != self.page_directory {
+            vmm::switch_address_space(self.page_directory);
+        }
+    }
+}
+
+/// Registers to start executing `entry` in ring 3, with the stack `user_stack`
+pub fn user_frame(entry: usize, user_stack: usize) -> InterruptFrame {
+    let data = gdt::USER_DATA_SELECTOR as u32;
+    InterruptFrame {
+        edi: 0,
+        esi: 0,
+        ebp: 0,
+        esp: 0,
+        ebx: 0,
+        edx: 0,
+        ecx: 0,
+        eax: 0,
+        gs: data,
+        fs: data,
+        es: data,
+        ds: data,
+        vector: 0,
+        error_code: 0,
+        eip: entry as u32,
+        cs: gdt::USER_CODE_SELECTOR as u32,
+        eflags: INITIAL_EFLAGS,
+        user_esp: user_stack as u32,
+        user_ss: gdt::USER_STACK_SELECTOR as u32,
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        if self.is_kernel_task() {
+            return;
+        }
+
+        // The heap pages are unmapped through the current page directory,
+        // no context switch may happen meanwhile
+        idt::without_interrupts(|| {
+            let previous = vmm::current_address_space();
+            vmm::switch_address_space(self.page_directory);
+            self.heap.take();
+            vmm::switch_address_space(previous);
+            vmm::destroy_address_space(self.page_directory).unwrap();
+        });
+    }
+}
+
+/// All the existing processes
+pub struct ProcessTable {
+    processes: Vec<Process>,
+    next_pid: Pid,
+    current: Option<Pid>,
+}
+
+impl ProcessTable {
+    /// Add a process, created by the current task
+    fn insert(&mut self, mut process: Process) -> Pid {
+        let pid = process.pid;
+        process.parent = self.current;
+        self.processes.push(process);
+        self.next_pid += 1;
+        pid
+    }
+
+    /// Register the code running since boot as the first task
+    ///
+    /// Its context is saved the first time it is preempted.
+    fn insert_boot_task(&mut self) -> Pid {
+        let mut process = Process::new(self.next_pid, "kernel", vmm::kernel_address_space(), None);
+        process.kernel_stack = None;
+        process.state = State::Running;
+        let pid = self.insert(process);
+        self.current = Some(pid);
+        pid
+    }
+
+    /// Create a task running `entry(arg)` in the kernel space
+    pub fn spawn_kernel_task(
+        &mut self,
+        name: &str,
+        entry: extern "C" fn(usize) -> !,
+        arg: usize,
+    ) -> Pid {
+        let mut process = Process::new(self.next_pid, name, vmm::kernel_address_space(), None);
+        process.start_kernel(entry, arg);
+        self.insert(process)
+    }
+
+    /// Create a process with an empty user space
+    ///
+    /// It stays blocked until started with `Process::start_user`.
+    pub fn spawn_user(&mut self, name: &str) -> Result<Pid, VirtualMemoryError> {
+        let heap = unsafe { Heap::new(USER_HEAP_START as *const usize, false) };
+        let process = Process::new(
+            self.next_pid,
+            name,
+            vmm::create_address_space()?,
+            Some(heap),
+        );
+        Ok(self.insert(process))
+    }
+
+    pub fn get(&self, pid: Pid) -> Option<&Process> {
+        self.processes.iter().find(|p| p.pid == pid)
+    }
+
+    pub fn get_mut(&mut self, pid: Pid) -> Option<&mut Process> {
+        self.processes.iter_mut().find(|p| p.pid == pid)
+    }
+
+    /// Take a process out of the table
+    ///
+    /// Its resources are freed once it is dropped.
+    pub fn remove(&mut self, pid: Pid) -> Option<Process> {
+        assert_ne!(Some(pid), self.current, "cannot remove the current process");
+        let idx = self.processes.iter().position(|p| p.pid == pid)?;
+        Some(self.processes.remove(idx))
+    }
+
+    /// Create a copy of the current process, starting at `frame`
+    ///
+    /// `page_directory` is a copy of its address space. The child gets 0 as
+    /// result of the interrupted system call.
+    fn fork(&mut self, frame: &InterruptFrame, page_directory: usize) -> Pid {
+        let pid = self.next_pid;
+        let parent = self.current_mut().unwrap();
+        // The heap content was copied with the rest of the user space
+        let heap = parent.heap.as_ref().map(|heap| unsafe { heap.duplicate() });
+        let mut child = Process::new(pid, &parent.name, page_directory, heap);
+        child.start(InterruptFrame { eax: 0, ..*frame });
+        self.insert(child)
+    }
+
+    /// Make a process a zombie with `status`
+    ///
+    /// Its children become orphans.
+    fn terminate(&mut self, pid: Pid, status: usize) {
+        for child in self.processes.iter_mut().filter(|p| p.parent == Some(pid)) {
+            child.parent = None;
+        }
+        self.get_mut(pid).unwrap().state = State::Zombie(status);
+    }
+
+    /// Terminate a user process with `KILLED_STATUS`
+    ///
+    /// A process interrupted in the kernel may hold locks: unless it is
+    /// blocked, or the caller, it is only terminated once back in ring 3.
+    fn kill(&mut self, pid: Pid) -> Result<(), ProcessError> {
+        let process = self.get(pid).ok_or(ProcessError::NoSuchProcess)?;
+        if process.is_kernel_task() {
+            return Err(ProcessError::KernelTask);
+        }
+        let in_user_mode = Some(pid) != self.current
+            && !process.context.is_null()
+            && unsafe { (*process.context).is_from_user() };
+        match process.state {
+            State::Zombie(_) => (),
+            State::Blocked => self.terminate(pid, KILLED_STATUS),
+            _ if Some(pid) == self.current || in_user_mode => self.terminate(pid, KILLED_STATUS),
+            _ => self.get_mut(pid).unwrap().killed = true,
+        }
+        Ok(())
+    }
+
+    /// Remove a zombie process and return its exit status
+    pub fn reap(&mut self, pid: Pid) -> Option<usize> {
+        match self.get(pid)?.state {
+            State::Zombie(status) => {
+                self.remove(pid);
+                Some(status)
+            }
+            _ => None,
+        }
+    }
+
+    /// Pid of the running process
+    pub fn current_pid(&self) -> Option<Pid> {
+        self.current
+    }
+
+    pub fn current_mut(&mut self) -> Option<&mut Process> {
+        let pid = self.current?;
+        self.get_mut(pid)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Process> {
+        self.processes.iter()
+    }
+
+    fn iter_mut(&mut self) -> impl Iterator<Item = &mut Process> {
+        self.processes.iter_mut()
+    }
+
+    /// Remove the zombies nobody will wait for
+    fn reap_orphans(&mut self) {
+        let current = self.current;
+        self.processes.retain(|p| match p.state {
+            State::Zombie(_) => p.parent.is_some() || Some(p.pid) == current,
+            _ => true,
+        });
+    }
+}
+
+use spin::Mutex;
+
+/// Unique source of truth for processes
+///
+/// Also used by the scheduler in interrupt handlers, so it must only be
+/// locked through `table` outside of them.
+static PROCESS_TABLE: Mutex<ProcessTable> = Mutex::new(ProcessTable {
+    processes: Vec::new(),
+    next_pid: 0,
+    current: None,
+});
+
+/// Access the process table, with interrupts disabled
+pub fn table<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut ProcessTable) -> R,
+{
+    idt::without_interrupts(|| f(&mut PROCESS_TABLE.lock()))
+}
+
+/// Woken up each time a process exits
+static EXITED: WaitQueue = WaitQueue::new();
+
+/// Terminate the running process
+///
+/// It becomes a zombie until collected. The CPU is given to another task at
+/// the end of the current interrupt.
+pub fn exit(status: usize) {
+    table(|t| {
+        let pid = t.current_pid().unwrap();
+        t.terminate(pid, status);
+    });
+    EXITED.wake_all();
+    scheduler::request_reschedule();
+}
+
+/// Terminate a user process, see `ProcessTable::kill`
+pub fn kill(pid: Pid) -> Result<(), ProcessError> {
+    let current = table(|t| {
+        t.kill(pid)?;
+        Ok(t.current_pid())
+    })?;
+    EXITED.wake_all();
+    if current == Some(pid) {
+        scheduler::request_reschedule();
+    }
+    Ok(())
+}
+
+/// Duplicate the current process, interrupted with `frame` in a system call
+///
+/// Return the pid of the child, which resumes from the same system call.
+pub fn fork(frame: &InterruptFrame) -> Result<Pid, ProcessError> {
+    if table(|t| t.current_mut().unwrap().is_kernel_task()) {
+        return Err(ProcessError::KernelTask);
+    }
+    let page_directory = vmm::clone_address_space().map_err(ProcessError::VirtualMemoryError)?;
+    Ok(table(|t| t.fork(frame, page_directory)))
+}
+
+/// Block until a child of the current process terminates, then collect it
+///
+/// Wait for the child `pid`, or for any child if `None`. Return its pid and
+/// exit status.
+pub fn wait_child(pid: Option<Pid>) -> Result<(Pid, usize), ProcessError> {
+    let parent = table(|t| t.current_pid().unwrap());
+    EXITED.wait_for(|| {
+        table(|t| {
+            let mut children = t
+                .iter()
+                .filter(|p| p.parent == Some(parent) && pid.map_or(true, |pid| p.pid == pid))
+                .peekable();
+            if children.peek().is_none() {
+                return Some(Err(ProcessError::NoChild));
+            }
+            let zombie = children.find_map(|p| match p.state {
+                State::Zombie(status) => Some((p.pid, status)),
+                _ => None,
+            });
+            zombie.map(|(pid, status)| {
+                t.remove(pid);
+                Ok((pid, status))
+            })
+        })
+    })
+}
+
+/// Collect the orphans which terminated
+pub fn reap_orphans() {
+    table(|t| t.reap_orphans());
+}
+
+/// Terminate the current process if it was killed while in the kernel
+///
+/// Called with the registers of an interrupt about to return in ring 3.
+pub fn check_killed(frame: &InterruptFrame) {
+    if frame.is_from_user() && table(|t| t.current_mut().map_or(false, |p| p.killed)) {
+        exit(KILLED_STATUS);
+    }
+}
+
+/// Block until `pid` terminates, then collect it
+///
+/// Return its exit status, `None` if there is no such process.
+pub fn wait(pid: Pid) -> Option<usize> {
+    EXITED.wait_for(|| {
+        table(|t| match t.get(pid) {
+            Some(_) => t.reap(pid).map(Some),
+            None => Some(None),
+        })
+    })
+}
@@ -0,0 +1,144 @@
+//! Preemptive round-robin scheduler
+//!
+//! Every task runs for a quantum of timer ticks before the CPU is given to
+//! the next ready one. The idle task runs when no other task is ready, and
+//! collects the orphans.
+//!
+//! Context switches happen at the end of interrupts: the registers of the
+//! interrupted task are saved on its kernel stack, and the interrupt returns
+//! to the saved registers of the next task.
+
+use super::{check_killed, reap_orphans, table, Pid, State};
+use crate::idt::{self, InterruptFrame};
+use crate::time;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Default number of ticks a task runs before being preempted
+pub const DEFAULT_QUANTUM: usize = 10;
+
+static QUANTUM: AtomicUsize = AtomicUsize::new(DEFAULT_QUANTUM);
+/// Ticks spent by the current task in its quantum
+static ELAPSED: AtomicUsize = AtomicUsize::new(0);
+static NEED_RESCHEDULE: AtomicBool = AtomicBool::new(false);
+static IDLE_PID: AtomicUsize = AtomicUsize::new(0);
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Start scheduling
+///
+/// The code running since boot becomes the first task, and the idle task is
+/// created. The timer must be running.
+pub fn init() {
+    table(|t| {
+        t.insert_boot_task();
+        IDLE_PID.store(t.spawn_kernel_task("idle", idle, 0), Ordering::Relaxed);
+    });
+    idt::register_handler(idt::YIELD_VECTOR, yield_handler);
+    time::set_interval(1, tick).unwrap();
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+extern "C" fn idle(_: usize) -> ! {
+    loop {
+        reap_orphans();
+        unsafe {
+            asm!("hlt", options(nomem, nostack));
+        }
+    }
+}
+
+/// Set the number of ticks a task runs before being preempted
+pub fn set_quantum(ticks: usize) {
+    assert!(ticks > 0, "the scheduler quantum can't be empty");
+    QUANTUM.store(ticks, Ordering::Relaxed);
+}
+
+pub fn quantum() -> usize {
+    QUANTUM.load(Ordering::Relaxed)
+}
+
+/// Give the CPU to another task at the end of the current interrupt
+pub fn request_reschedule() {
+    NEED_RESCHEDULE.store(true, Ordering::Relaxed);
+}
+
+/// Give the CPU to another task now
+///
+/// The current task is resumed later if it is still ready, or once woken up
+/// if it is blocked. Also usable with interrupts disabled, which are disabled
+/// again when it resumes.
+pub fn yield_now() {
+    unsafe {
+        asm!("int 0x81", options(nomem, nostack));
+    }
+}
+
+fn yield_handler(_frame: &mut InterruptFrame) {
+    request_reschedule();
+}
+
+fn tick() {
+    table(|t| {
+        if let Some(current) = t.current_mut() {
+            current.ticks += 1;
+        }
+    });
+    if ELAPSED.fetch_add(1, Ordering::Relaxed) + 1 >= quantum() {
+        request_reschedule();
+    }
+}
+
+/// Pick the next ready task after `current`, in table order
+fn next_task(current: Option<Pid>) -> Pid {
+    let idle = IDLE_PID.load(Ordering::Relaxed);
+    table(|t| {
+        let ready = |pid: Pid| pid != idle && t.get(pid).unwrap().state == State::Ready;
+        let pids = || t.iter().map(|p| p.pid);
+        let after = pids()
+            .skip_while(|&pid| Some(pid) != current)
+            .skip(1)
+            .find(|&pid| ready(pid));
+        after
+            .or_else(|| pids().find(|&pid| ready(pid)))
+            .or_else(|| match current {
+                // Keep running if nothing else is ready
+                Some(pid) if t.get(pid).unwrap().state == State::Running => Some(pid),
+                _ => None,
+            })
+            .unwrap_or(idle)
+    })
+}
+
+/// Switch to the next task if requested
+///
+/// Called at the end of every interrupt with the interrupted task registers.
+/// Return the registers to restore.
+pub fn schedule(frame: *mut InterruptFrame) -> *mut InterruptFrame {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return frame;
+    }
+    check_killed(unsafe { &*frame });
+    if !NEED_RESCHEDULE.swap(false, Ordering::Relaxed) {
+        return frame;
+    }
+    ELAPSED.store(0, Ordering::Relaxed);
+
+    let current = table(|t| t.current_pid());
+    let next = next_task(current);
+    if Some(next) == current {
+        return frame;
+    }
+
+    table(|t| {
+        if let Some(process) = t.current_mut() {
+            process.context = frame;
+            if process.state == State::Running {
+                process.state = State::Ready;
+            }
+        }
+        let process = t.get_mut(next).unwrap();
+        process.state = State::Running;
+        process.load();
+        t.current = Some(next);
+        t.get(next).unwrap().context
+    })
+}
@@ -0,0 +1,108 @@
+//! Wait queues
+//!
+//! A task waiting for an event blocks on a queue instead of spinning: it is
+//! not scheduled until the event source wakes the queue up.
+//!
+//! Blocked tasks record the queue they wait on in the process table, so
+//! neither waiting nor waking up touches the heap.
+
+use super::{scheduler, table, Process, State};
+use crate::idt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Place of a blocked task in a wait queue
+#[derive(Debug, Copy, Clone)]
+pub(super) struct Waiter {
+    /// Address of the queue
+    queue: usize,
+    /// Rank of arrival in the queue
+    ticket: usize,
+}
+
+/// Tasks blocked on an event
+///
+/// May be woken up from interrupt handlers.
+pub struct WaitQueue {
+    next_ticket: AtomicUsize,
+}
+
+impl WaitQueue {
+    pub const fn new() -> WaitQueue {
+        WaitQueue {
+            next_ticket: AtomicUsize::new(0),
+        }
+    }
+
+    fn id(&self) -> usize {
+        self as *const WaitQueue as usize
+    }
+
+    fn is_waiting(&self, process: &Process) -> bool {
+        process.state == State::Blocked
+            && process
+                .waiter
+                .map_or(false, |waiter| waiter.queue == self.id())
+    }
+
+    /// Block the current task until `ready` returns a value
+    ///
+    /// `ready` is checked with interrupts disabled, so a wake up can't be
+    /// missed between the check and the sleep. The scheduler must be running.
+    pub fn wait_for<T, F>(&self, mut ready: F) -> T
+    where
+        F: FnMut() -> Option<T>,
+    {
+        idt::without_interrupts(|| loop {
+            if let Some(value) = ready() {
+                return value;
+            }
+            table(|t| {
+                let current = t.current_mut().expect("no task to block");
+                current.state = State::Blocked;
+                current.waiter = Some(Waiter {
+                    queue: self.id(),
+                    ticket: self.next_ticket.fetch_add(1, Ordering::Relaxed),
+                });
+            });
+            scheduler::yield_now();
+        })
+    }
+
+    /// Block the current task until `condition` is true
+    pub fn wait_until<F>(&self, mut condition: F)
+    where
+        F: FnMut() -> bool,
+    {
+        self.wait_for(|| if condition() { Some(()) } else { None })
+    }
+
+    /// Make every waiting task ready again
+    ///
+    /// They check their condition again once scheduled.
+    pub fn wake_all(&self) {
+        table(|t| {
+            for process in t.iter_mut().filter(|p| self.is_waiting(p)) {
+                wake(process);
+            }
+        });
+    }
+
+    /// Make the longest waiting task ready again
+    pub fn wake_one(&self) {
+        table(|t| {
+            let first = t
+                .iter_mut()
+                .filter(|p| self.is_waiting(p))
+                .min_by_key(|p| p.waiter.map(|waiter| waiter.ticket));
+            if let Some(process) = first {
+                wake(process);
+            }
+        });
+    }
+}
+
+/// Take a blocked task out of its queue
+fn wake(process: &mut Process) {
+    process.waiter = None;
+    process.state = State::Ready;
+}
@@ -3,9 +3,31 @@
 //! # Features
 //! - Proper initialisation routine
 //! - Read scan codes from the buffer
+//! - Interrupt driven keyboard input
+//! - Timeouts on the controller status polling
 
+use crate::idt::{self, InterruptFrame};
 use crate::io_port;
+use crate::pic;
+use crate::process::WaitQueue;
+use crate::ring_buffer::RingBuffer;
 use crate::spin::Mutex;
+use crate::time;
+
+/// IRQ line of the first PS/2 port
+const KEYBOARD_IRQ: u8 = 1;
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64;
+/// Maximum time to wait for the controller, in milliseconds
+const TIMEOUT_MS: usize = 100;
+
+#[derive(Debug, Copy, Clone)]
+pub enum Ps2Error {
+    /// The controller didn't answer in time
+    Timeout,
+    /// No port passed the interface test
+    NoFunctionalPort,
+}
 
 /// The PS/2 port representation
 pub struct Ps2 {
@@ -22,40 +44,48 @@ impl Ps2 {
         self.helper.write(cmd);
     }
 
-    /// Read scan code from the PS/2 buffer
-    pub fn read(&self) -> u8 {
-        let mut s = self.status();
-        while s & 1 == 0 {
-            s = self.status();
+    /// Wait until `ready` is true for the status register
+    fn wait_status(&self, ready: fn(u8) -> bool) -> Result<(), Ps2Error> {
+        let start = time::ticks();
+        while !ready(self.status()) {
+            if time::elapsed_ms(start) > TIMEOUT_MS {
+                return Err(Ps2Error::Timeout);
+            }
         }
-        self.buffer.read()
+        Ok(())
     }
 
-    fn write(&self, value: u8) {
-        let mut s = self.status();
-        while s & 1 << 1 != 0 {
-            s = self.status();
-        }
+    /// Read scan code from the PS/2 buffer
+    ///
+    /// Fail if the output buffer stays empty for too long.
+    pub fn read(&self) -> Result<u8, Ps2Error> {
+        self.wait_status(|s| s & 1 != 0)?;
+        Ok(self.buffer.read())
+    }
+
+    fn write(&self, value: u8) -> Result<(), Ps2Error> {
+        self.wait_status(|s| s & 1 << 1 == 0)?;
         self.buffer.write(value);
+        Ok(())
     }
 
-    fn get_config(&self) -> u8 {
+    fn get_config(&self) -> Result<u8, Ps2Error> {
         self.command(0x20);
         self.read()
     }
 
-    fn set_config(&self, conf: u8) {
+    fn set_config(&self, conf: u8) -> Result<(), Ps2Error> {
         self.command(0x60);
-        self.write(conf);
+        self.write(conf)
     }
 
-    fn interface_test_ok(&self, cmd: u8, n: u8) -> bool {
+    fn interface_test_ok(&self, cmd: u8, n: u8) -> Result<bool, Ps2Error> {
         self.command(cmd);
         print!("    Port {}: ", n);
-        match self.read() {
+        match self.read()? {
             0x0 => {
                 println!("OK");
-                return true;
+                return Ok(true);
             }
             0x01 => println!("Failed: clock line stuck low"),
             0x02 => println!("Failed: clock line stuck high"),
@@ -63,27 +93,34 @@ impl Ps2 {
             0x04 => println!("Failed: data line stuck high"),
             _ => println!("Failed: unknown error"),
         }
-        false
+        Ok(false)
     }
 
     /// Initialise the PS/2 controler with proper config
     ///
-    /// Must be executed before use.
+    /// Must be executed before use, with the timer running.
     pub fn init(&self) {
+        match self.setup() {
+            Ok(()) => println!("PS/2 successfully initialized.\n"),
+            Err(e) => println!("PS/2 initialization failed: {:?}\n", e),
+        }
+    }
+
+    fn setup(&self) -> Result<(), Ps2Error> {
         self.command(0xAD);
         self.command(0xA7);
         self.buffer.read();
 
-        self.set_config(self.get_config() & 0xBC); //0b10111100
+        self.set_config(self.get_config()? & 0xBC)?; //0b10111100
         self.command(0xAA);
-        match self.read() {
+        match self.read()? {
             0x55 => println!("PS/2 controller self test passed"),
             _ => println!("PS/2 controller self test failed"),
         };
 
         let mut is_dual_port = false;
         self.command(0xA8);
-        match self.get_config() {
+        match self.get_config()? {
             conf if conf & 1 << 5 == 0 => {
                 is_dual_port = true;
                 self.command(0xA7);
@@ -93,16 +130,15 @@ impl Ps2 {
 
         println!("Interface test");
         let mut count_available_port = 0u8;
-        count_available_port |= self.interface_test_ok(0xAB, 1) as u8;
+        count_available_port |= self.interface_test_ok(0xAB, 1)? as u8;
         if is_dual_port {
-            count_available_port |= (self.interface_test_ok(0xA9, 2) as u8) << 1;
+            count_available_port |= (self.interface_test_ok(0xA9, 2)? as u8) << 1;
         }
         if count_available_port == 0 {
-            println!("PS/2 have no functional interface.");
-            return;
+            return Err(Ps2Error::NoFunctionalPort);
         }
 
-        let mut conf = self.get_config();
+        let mut conf = self.get_config()?;
         if count_available_port & 1 != 0 {
             self.command(0xAE);
             conf |= 1;
@@ -111,14 +147,38 @@ impl Ps2 {
             self.command(0xA8);
             conf |= 1 << 1;
         }
-        self.set_config(conf);
+        self.set_config(conf)?;
 
-        println!("PS/2 successfully initialized.\n");
+        if count_available_port & 1 != 0 {
+            idt::register_handler(pic::vector(KEYBOARD_IRQ), keyboard_interrupt_handler);
+            pic::unmask(KEYBOARD_IRQ);
+        }
+
+        Ok(())
     }
 }
 
 /// The PS/2 controler used by our kernel
 pub static PS2: Mutex<Ps2> = Mutex::new(Ps2 {
-    buffer: io_port::Port::new(0x60),
-    helper: io_port::Port::new(0x64),
+    buffer: io_port::Port::new(DATA_PORT),
+    helper: io_port::Port::new(STATUS_PORT),
 });
+
+/// Scan codes received from the keyboard, not yet interpreted
+pub static SCAN_CODES: RingBuffer = RingBuffer::new();
+
+/// Tasks waiting for keyboard input
+static KEYBOARD_INPUT: WaitQueue = WaitQueue::new();
+
+/// Take the next scan code, blocking until a key is pressed
+pub fn next_scan_code() -> u8 {
+    KEYBOARD_INPUT.wait_for(|| SCAN_CODES.pop())
+}
+
+fn keyboard_interrupt_handler(_frame: &mut InterruptFrame) {
+    // Don't go through PS2, its lock may be held by the interrupted code
+    let scan_code = io_port::Port::<u8>::new(DATA_PORT).read();
+    // Keystrokes are dropped when the buffer is full
+    SCAN_CODES.push(scan_code).ok();
+    KEYBOARD_INPUT.wake_all();
+}
@@ -0,0 +1,65 @@
+//! Lock-free ring buffer
+//!
+//! Single producer, single consumer queue of bytes. Designed to move data out
+//! of interrupt handlers without taking any lock.
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const CAPACITY: usize = 256;
+
+/// Fixed size byte queue
+///
+/// One slot is always kept empty to distinguish between a full and an empty
+/// buffer, so at most `CAPACITY - 1` bytes can be stored.
+pub struct RingBuffer {
+    buffer: UnsafeCell<[u8; CAPACITY]>,
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+/// Safe as long as there is only one producer and one consumer
+unsafe impl Sync for RingBuffer {}
+
+impl RingBuffer {
+    pub const fn new() -> RingBuffer {
+        RingBuffer {
+            buffer: UnsafeCell::new([0; CAPACITY]),
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    /// Append a byte
+    ///
+    /// Give the byte back if the buffer is full.
+    pub fn push(&self, value: u8) -> Result<(), u8> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % CAPACITY;
+
+        if next == self.head.load(Ordering::Acquire) {
+            return Err(value);
+        }
+        unsafe {
+            (*self.buffer.get())[tail] = value;
+        }
+        self.tail.store(next, Ordering::Release);
+        Ok(())
+    }
+
+    /// Take the oldest byte
+    pub fn pop(&self) -> Option<u8> {
+        let head = self.head.load(Ordering::Relaxed);
+
+        if head == self.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        let value = unsafe { (*self.buffer.get())[head] };
+        self.head.store((head + 1) % CAPACITY, Ordering::Release);
+        Some(value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire) == self.tail.load(Ordering::Acquire)
+    }
+}
@@ -0,0 +1,75 @@
+//! Serial port driver
+//!
+//! Output only, on the first 16550 UART, at 38400 bauds with 8 data bits, no
+//! parity and 1 stop bit.
+
+use crate::io_port::Port;
+use core::fmt;
+use spin::Mutex;
+
+const COM1: u16 = 0x3F8;
+
+/// Line status register bit: the transmitter holding register is empty
+const LSR_THR_EMPTY: u8 = 0x20;
+
+pub struct SerialPort {
+    data: Port<u8>,
+    interrupt_enable: Port<u8>,
+    fifo_control: Port<u8>,
+    line_control: Port<u8>,
+    modem_control: Port<u8>,
+    line_status: Port<u8>,
+}
+
+impl SerialPort {
+    const fn new(base: u16) -> SerialPort {
+        SerialPort {
+            data: Port::new(base),
+            interrupt_enable: Port::new(base + 1),
+            fifo_control: Port::new(base + 2),
+            line_control: Port::new(base + 3),
+            modem_control: Port::new(base + 4),
+            line_status: Port::new(base + 5),
+        }
+    }
+
+    fn init(&self) {
+        self.interrupt_enable.write(0x00);
+        // Set the baud rate divisor, 115200 / 3
+        self.line_control.write(0x80);
+        self.data.write(0x03);
+        self.interrupt_enable.write(0x00);
+        // 8 bits, no parity, one stop bit
+        self.line_control.write(0x03);
+        // Enable and clear the FIFOs, 14 bytes threshold
+        self.fifo_control.write(0xC7);
+        // Data terminal ready, request to send
+        self.modem_control.write(0x03);
+    }
+
+    pub fn write_byte(&self, byte: u8) {
+        while self.line_status.read() & LSR_THR_EMPTY == 0 {}
+        self.data.write(byte);
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if byte == b'\n' {
+                self.write_byte(b'\r');
+            }
+            self.write_byte(byte);
+        }
+        Ok(())
+    }
+}
+
+/// The first serial port, once initialized
+pub static SERIAL: Mutex<Option<SerialPort>> = Mutex::new(None);
+
+pub fn init() {
+    let port = SerialPort::new(COM1);
+    port.init();
+    SERIAL.lock().replace(port);
+}
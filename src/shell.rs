@@ -3,11 +3,17 @@
 //! Handle a set of basic user instructions.
 
 use crate::debug;
+use crate::initrd;
 use crate::power_management;
-use crate::writer::WRITER;
+use crate::process::{self, scheduler};
+use crate::time;
+use crate::user_mode;
+use crate::writer;
 use core::str::SplitWhitespace;
 
+use alloc::format;
 use alloc::prelude::v1::Vec;
+use alloc::string::String;
 use spin::Mutex;
 
 static LAST_COMMAND: Mutex<Option<Vec<u8>>> = Mutex::new(None);
@@ -19,14 +25,28 @@ static LAST_COMMAND: Mutex<Option<Vec<u8>>> = Mutex::new(None);
 /// # Valid instructions
 /// - shutdown
 /// - reboot
+/// - uptime
+/// - date
+/// - usermode \[hello|fault|family|\<initrd file\>\] \[args...\]
+/// - initrd
+/// - cat \<initrd file\>
+/// - ps
+/// - kill \<pid\>
 /// - dump
 ///     - seg_reg
 ///     - gdtr
+///     - idtr
 ///     - stack \[max\]
 ///     - trace \[max\]
+///     - buddy
+///     - multiboot
+///     - cmdline
+///     - pagedir
+///     - pagetable \<index\>
+///     - mappings
 ///
 pub fn execute() {
-    let ascii_line = WRITER.lock().as_ref().unwrap().get_bottom_line();
+    let ascii_line = writer::screen(|screen| screen.get_bottom_line());
     println!();
 
     let mut words = match core::str::from_utf8(&ascii_line) {
@@ -39,7 +59,14 @@ pub fn execute() {
         Some("dump") => dump(words),
         Some("shutdown") => power_management::shutdown(),
         Some("reboot") => power_management::reboot(),
-        Some("clear") => WRITER.lock().as_mut().unwrap().clear_screen(),
+        Some("clear") => writer::screen(|screen| screen.clear_screen()),
+        Some("uptime") => uptime(),
+        Some("date") => println!("{}", time::DateTime::now()),
+        Some("usermode") => usermode(words),
+        Some("ps") => ps(),
+        Some("kill") => kill(words),
+        Some("initrd") => list_initrd(),
+        Some("cat") => cat(words),
         _ => (),
     };
 
@@ -52,7 +79,77 @@ pub fn execute() {
 /// executed
 pub fn load_last_command() {
     if let Some(cmd) = &*LAST_COMMAND.lock() {
-        WRITER.lock().as_mut().unwrap().swap_bottom_line(cmd);
+        writer::screen(|screen| screen.swap_bottom_line(cmd));
+    }
+}
+
+fn uptime() {
+    let uptime = time::uptime();
+    println!("up {}.{:03}s", uptime.as_secs(), uptime.subsec_millis());
+}
+
+fn ps() {
+    println!("quantum: {} ticks", scheduler::quantum());
+    println!("  PID  PPID STATE         TICKS NAME");
+    process::table(|t| {
+        for p in t.iter() {
+            println!(
+                "{:>5} {:>5} {:<13} {:>5} {}",
+                p.pid,
+                p.parent.map_or(String::from("-"), |pid| format!("{}", pid)),
+                format!("{}", p.state),
+                p.ticks,
+                p.name
+            );
+        }
+    });
+}
+
+fn kill(mut words: SplitWhitespace) {
+    let pid = match words.next().map(|word| word.parse()) {
+        Some(Ok(pid)) => pid,
+        _ => {
+            println!("Usage: kill <pid>");
+            return;
+        }
+    };
+    if let Err(e) = process::kill(pid) {
+        println!("Cannot kill {}: {:?}", pid, e);
+    }
+}
+
+fn list_initrd() {
+    for file in initrd::files() {
+        println!("{:>8} {}", file.data.len(), file.name);
+    }
+}
+
+fn cat(mut words: SplitWhitespace) {
+    let data = match words.next().and_then(initrd::get) {
+        Some(data) => data,
+        None => {
+            println!("Usage: cat <initrd file>");
+            return;
+        }
+    };
+    for &byte in data {
+        print!("{}", byte as char);
+    }
+}
+
+fn usermode(mut words: SplitWhitespace) {
+    let name = words.next().unwrap_or("hello");
+    let argv: Vec<&str> = core::iter::once(name).chain(words).collect();
+    let program = match user_mode::find_program(name) {
+        Some(program) => program,
+        None => {
+            println!("Unknown program");
+            return;
+        }
+    };
+    match user_mode::run(name, program, &argv, &[]) {
+        Ok(status) => println!("User program exited with status {}", status),
+        Err(e) => println!("Cannot run user program: {:?}", e),
     }
 }
 
@@ -61,9 +158,17 @@ fn dump(mut words: SplitWhitespace) {
         Some("seg_reg") => debug::dump_segment_registers(),
         Some("gdtr") => debug::dump_gdtr(),
         Some("gdt") => debug::dump_gdt(),
+        Some("idtr") => debug::dump_idtr(),
+        Some("idt") => debug::dump_idt(),
         Some("stack") => debug::dump_stack(get_number(words)),
         Some("trace") => debug::stack_trace(get_number(words)),
         Some("bitmap") => debug::dump_bitmap(),
+        Some("buddy") => debug::dump_buddy(),
+        Some("multiboot") => debug::dump_multiboot(),
+        Some("cmdline") => debug::dump_cmdline(),
+        Some("pagedir") => debug::dump_page_directory(),
+        Some("pagetable") => debug::dump_page_table(get_number(words)),
+        Some("mappings") => debug::dump_mappings(),
         _ => (),
     };
 }
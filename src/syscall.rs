@@ -0,0 +1,291 @@
+//! System calls
+//!
+//! User programs call the kernel with `int 0x80`.
+//!
+//! # ABI
+//! - eax: system call number
+//! - ebx, ecx, edx, esi, edi: arguments
+//! - eax on return: the result, or a negated `SyscallError` code
+//!
+//! # System calls
+//! - 0: exit(status)
+//! - 1: read(fd, buffer, length)
+//! - 2: write(fd, buffer, length)
+//! - 3: getpid()
+//! - 4: sbrk(increment)
+//! - 5: fork()
+//! - 6: exec(name, length)
+//! - 7: waitpid(pid, status), any child if pid is -1
+//! - 8: kill(pid)
+
+use crate::idt::{self, InterruptFrame};
+use crate::keyboard::{self, Command, KEYBOARD};
+use crate::process::{self, ProcessError};
+use crate::ps2::{self, SCAN_CODES};
+use crate::user_mode::{self, UserModeError};
+use crate::virtual_memory_management::{self as vmm, PageFlags};
+use crate::writer;
+use alloc::string::String;
+use core::slice;
+
+pub const SYS_EXIT: usize = 0;
+pub const SYS_READ: usize = 1;
+pub const SYS_WRITE: usize = 2;
+pub const SYS_GETPID: usize = 3;
+pub const SYS_SBRK: usize = 4;
+pub const SYS_FORK: usize = 5;
+pub const SYS_EXEC: usize = 6;
+pub const SYS_WAITPID: usize = 7;
+pub const SYS_KILL: usize = 8;
+
+const STDIN: usize = 0;
+const STDOUT: usize = 1;
+const STDERR: usize = 2;
+
+#[derive(Debug, Copy, Clone)]
+pub enum SyscallError {
+    InvalidSyscall = 1,
+    BadAddress = 2,
+    BadFileDescriptor = 3,
+    OutOfMemory = 4,
+    NoSuchProcess = 5,
+    NoChild = 6,
+    PermissionDenied = 7,
+    NotFound = 8,
+}
+
+impl From<ProcessError> for SyscallError {
+    fn from(e: ProcessError) -> SyscallError {
+        match e {
+            ProcessError::NoSuchProcess => SyscallError::NoSuchProcess,
+            ProcessError::NoChild => SyscallError::NoChild,
+            ProcessError::KernelTask => SyscallError::PermissionDenied,
+            ProcessError::VirtualMemoryError(_) => SyscallError::OutOfMemory,
+        }
+    }
+}
+
+impl From<UserModeError> for SyscallError {
+    fn from(_: UserModeError) -> SyscallError {
+        SyscallError::OutOfMemory
+    }
+}
+
+enum Syscall {
+    /// Only uses its arguments
+    Args(fn(usize, usize, usize, usize, usize) -> Result<usize, SyscallError>),
+    /// Also changes the registers of the caller
+    Frame(fn(&mut InterruptFrame) -> Result<usize, SyscallError>),
+}
+
+static SYSCALL_TABLE: [Syscall; 9] = [
+    Syscall::Args(sys_exit),
+    Syscall::Args(sys_read),
+    Syscall::Args(sys_write),
+    Syscall::Args(sys_getpid),
+    Syscall::Args(sys_sbrk),
+    Syscall::Frame(sys_fork),
+    Syscall::Frame(sys_exec),
+    Syscall::Args(sys_waitpid),
+    Syscall::Args(sys_kill),
+];
+
+/// Enable system calls
+pub fn init() {
+    idt::register_handler(idt::SYSCALL_VECTOR, syscall_handler);
+}
+
+fn syscall_handler(frame: &mut InterruptFrame) {
+    let ret = match SYSCALL_TABLE.get(frame.eax as usize) {
+        Some(Syscall::Args(syscall)) => syscall(
+            frame.ebx as usize,
+            frame.ecx as usize,
+            frame.edx as usize,
+            frame.esi as usize,
+            frame.edi as usize,
+        ),
+        Some(Syscall::Frame(syscall)) => syscall(frame),
+        None => Err(SyscallError::InvalidSyscall),
+    };
+    frame.eax = match ret {
+        Ok(value) => value as u32,
+        Err(e) => -(e as i32) as u32,
+    };
+}
+
+/// Check that a user page allows the access, a copy on write page being
+/// writable
+fn is_accessible(flags: PageFlags, write: bool) -> bool {
+    let writable = flags.intersects(PageFlags::WRITABLE | PageFlags::COPY_ON_WRITE);
+    flags.contains(PageFlags::PRESENT | PageFlags::USER) && (!write || writable)
+}
+
+/// Check that a buffer is fully accessible to the user
+///
+/// Every page it covers must be mapped with the user flag in the current
+/// page directory, and be writable if `write` is set. Pages of a demand paged
+/// region not accessed yet are backed. Copy on write pages are copied when
+/// the kernel writes them.
+fn user_buffer(
+    address: usize,
+    length: usize,
+    write: bool,
+) -> Result<&'static mut [u8], SyscallError> {
+    let end = address
+        .checked_add(length)
+        .ok_or(SyscallError::BadAddress)?;
+    let mut page = address & !0xFFF;
+    while page < end {
+        let flags = vmm::current_directory(|directory| directory.get_page_flags(page));
+        match flags {
+            Some(flags) if is_accessible(flags, write) => (),
+            None if vmm::handle_fault(page, write, true) => (),
+            _ => return Err(SyscallError::BadAddress),
+        }
+        page += 0x1000;
+    }
+    Ok(unsafe { slice::from_raw_parts_mut(address as *mut u8, length) })
+}
+
+/// Terminate the calling process
+///
+/// The scheduler switches to another task when the system call returns.
+fn sys_exit(status: usize, _: usize, _: usize, _: usize, _: usize) -> Result<usize, SyscallError> {
+    process::exit(status);
+    Ok(0)
+}
+
+/// Read characters typed on the keyboard
+///
+/// Block until at least one character is available. Stop at the end of a
+/// line. Typed characters are echoed on the screen.
+fn sys_read(
+    fd: usize,
+    buffer: usize,
+    length: usize,
+    _: usize,
+    _: usize,
+) -> Result<usize, SyscallError> {
+    if fd != STDIN {
+        return Err(SyscallError::BadFileDescriptor);
+    }
+    let buffer = user_buffer(buffer, length, true)?;
+
+    let mut count = 0;
+    while count < length {
+        let scan_code = match SCAN_CODES.pop() {
+            Some(scan_code) => scan_code,
+            None if count > 0 => break,
+            None => ps2::next_scan_code(),
+        };
+        let c = match KEYBOARD.lock().handle_scan_code(scan_code as usize) {
+            keyboard::Key::Character(c) if c != 0x0 as char => c as u8,
+            keyboard::Key::Command(Command::Enter) => b'\n',
+            _ => continue,
+        };
+        print!("{}", c as char);
+        buffer[count] = c;
+        count += 1;
+        if c == b'\n' {
+            break;
+        }
+    }
+    Ok(count)
+}
+
+fn sys_write(
+    fd: usize,
+    buffer: usize,
+    length: usize,
+    _: usize,
+    _: usize,
+) -> Result<usize, SyscallError> {
+    if fd != STDOUT && fd != STDERR {
+        return Err(SyscallError::BadFileDescriptor);
+    }
+    let buffer = user_buffer(buffer, length, false)?;
+
+    writer::screen(|screen| {
+        for &byte in buffer.iter() {
+            screen.write_byte(byte);
+        }
+    });
+    Ok(length)
+}
+
+fn sys_getpid(_: usize, _: usize, _: usize, _: usize, _: usize) -> Result<usize, SyscallError> {
+    Ok(process::table(|t| t.current_pid().unwrap()))
+}
+
+/// Move the user heap break
+///
+/// The break is moved by whole pages. Return the previous break.
+fn sys_sbrk(
+    increment: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> Result<usize, SyscallError> {
+    process::table(|t| {
+        t.current_mut()
+            .unwrap()
+            .heap()
+            .ok_or(SyscallError::OutOfMemory)?
+            .sbrk(increment as isize)
+            .map_err(|_| SyscallError::OutOfMemory)
+    })
+}
+
+/// Duplicate the calling process
+///
+/// Return the child pid to the parent, and 0 to the child.
+fn sys_fork(frame: &mut InterruptFrame) -> Result<usize, SyscallError> {
+    Ok(process::fork(frame)?)
+}
+
+/// Replace the program of the calling process by a built-in one, or an initrd
+/// file
+///
+/// The program only gets its name as argument. Only return on failure, the
+/// new program starts from its entry point.
+fn sys_exec(frame: &mut InterruptFrame) -> Result<usize, SyscallError> {
+    let name = user_buffer(frame.ebx as usize, frame.ecx as usize, false)?;
+    // Copied in the kernel space, the user space is about to be replaced
+    let name = String::from(core::str::from_utf8(name).map_err(|_| SyscallError::NotFound)?);
+    let program = user_mode::find_program(&name).ok_or(SyscallError::NotFound)?;
+    user_mode::exec(frame, &name, program, &[&name], &[])?;
+    // The registers were reset, this is the new program initial eax
+    Ok(0)
+}
+
+/// Wait for the termination of a child
+///
+/// The exit status is written at `status`, unless it is null. Return the pid
+/// of the collected child.
+fn sys_waitpid(
+    pid: usize,
+    status: usize,
+    _: usize,
+    _: usize,
+    _: usize,
+) -> Result<usize, SyscallError> {
+    let status = match status {
+        0 => None,
+        address => Some(user_buffer(address, 4, true)?),
+    };
+    let pid = match pid as isize {
+        -1 => None,
+        pid => Some(pid as usize),
+    };
+    let (pid, exit_status) = process::wait_child(pid)?;
+    if let Some(status) = status {
+        status.copy_from_slice(&(exit_status as u32).to_le_bytes());
+    }
+    Ok(pid)
+}
+
+fn sys_kill(pid: usize, _: usize, _: usize, _: usize, _: usize) -> Result<usize, SyscallError> {
+    process::kill(pid)?;
+    Ok(0)
+}
@@ -0,0 +1,215 @@
+//! Time keeping
+//!
+//! # Features
+//! - Ticks counter driven by the PIT or the RTC
+//! - Uptime and active sleep
+//! - One-shot and periodic timer callbacks
+//! - Wall-clock date
+
+pub mod pit;
+pub mod rtc;
+
+pub use self::rtc::DateTime;
+
+use crate::idt::{self, InterruptFrame};
+use crate::pic;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use spin::Mutex;
+
+/// Number of ticks per second when driven by the PIT
+pub const PIT_FREQUENCY: u32 = 1000;
+const TIMER_IRQ: u8 = 0;
+const MAX_TIMERS: usize = 16;
+
+/// Ticks elapsed since the timer initialization
+///
+/// Wraps around after about 49 days, use `wrapping_sub` to compare values.
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of ticks per second, set by `init`
+static TICK_FREQUENCY: AtomicUsize = AtomicUsize::new(PIT_FREQUENCY as usize);
+
+/// Hardware generating the ticks
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TickSource {
+    /// Channel 0 of the PIT, on IRQ0
+    Pit,
+    /// Periodic interrupt of the RTC, on IRQ8
+    Rtc,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum TimerError {
+    NoTimerAvailable,
+    InvalidTimer,
+}
+
+/// A function called from the timer interrupt handler
+///
+/// Run with interrupts disabled, it must be short and not take locks that
+/// could be held by the interrupted code.
+pub type Callback = fn();
+
+#[derive(Copy, Clone)]
+struct Timer {
+    /// Tells apart the timers reusing a slot
+    generation: usize,
+    deadline: usize,
+    period: Option<usize>,
+    callback: Callback,
+}
+
+/// Handle to a registered timer
+///
+/// Its slot may be reused once the timer is gone, the generation tells the
+/// new timer apart.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TimerId {
+    index: usize,
+    generation: usize,
+}
+
+static NEXT_GENERATION: AtomicUsize = AtomicUsize::new(0);
+static TIMERS: Mutex<[Option<Timer>; MAX_TIMERS]> = Mutex::new([None; MAX_TIMERS]);
+
+/// Start counting ticks
+///
+/// With the PIT, channel 0 is programmed at `PIT_FREQUENCY` and IRQ0 is
+/// unmasked. With the RTC, its periodic interrupt is enabled at
+/// `rtc::PERIODIC_FREQUENCY`.
+pub fn init(source: TickSource) {
+    match source {
+        TickSource::Pit => {
+            TICK_FREQUENCY.store(PIT_FREQUENCY as usize, Ordering::Relaxed);
+            pit::init(PIT_FREQUENCY);
+            idt::register_handler(pic::vector(TIMER_IRQ), timer_interrupt_handler);
+            pic::unmask(TIMER_IRQ);
+        }
+        TickSource::Rtc => {
+            TICK_FREQUENCY.store(rtc::PERIODIC_FREQUENCY as usize, Ordering::Relaxed);
+            rtc::enable_periodic_interrupt(tick);
+        }
+    }
+}
+
+/// Number of ticks per second
+pub fn tick_frequency() -> usize {
+    TICK_FREQUENCY.load(Ordering::Relaxed)
+}
+
+fn timer_interrupt_handler(_frame: &mut InterruptFrame) {
+    tick();
+}
+
+/// Increment the ticks counter and fire the expired timers
+///
+/// Called by the interrupt handler of the current tick source.
+pub fn tick() {
+    let now = TICKS.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+
+    let mut expired: [Option<Callback>; MAX_TIMERS] = [None; MAX_TIMERS];
+    {
+        let mut timers = TIMERS.lock();
+        for (slot, callback) in timers.iter_mut().zip(expired.iter_mut()) {
+            if let Some(timer) = slot {
+                if now.wrapping_sub(timer.deadline) as isize >= 0 {
+                    *callback = Some(timer.callback);
+                    match timer.period {
+                        Some(period) => timer.deadline = now.wrapping_add(period),
+                        None => *slot = None,
+                    }
+                }
+            }
+        }
+    }
+
+    // Callbacks are run once the lock is released, so they can manage timers
+    for callback in expired.iter().flatten() {
+        callback();
+    }
+}
+
+/// Ticks elapsed since boot
+pub fn ticks() -> usize {
+    TICKS.load(Ordering::Relaxed)
+}
+
+fn ms_to_ticks(ms: usize) -> usize {
+    (ms as u64 * tick_frequency() as u64 / 1000) as usize
+}
+
+/// Milliseconds elapsed since an earlier `ticks()` value
+pub fn elapsed_ms(since: usize) -> usize {
+    (ticks().wrapping_sub(since) as u64 * 1000 / tick_frequency() as u64) as usize
+}
+
+/// Time elapsed since the timer initialization
+pub fn uptime() -> Duration {
+    let ticks = ticks() as u64;
+    Duration::from_millis(ticks * 1000 / tick_frequency() as u64)
+}
+
+/// Wait for at least `ms` milliseconds
+///
+/// The CPU is halted between ticks. Interrupts must be enabled.
+pub fn sleep_ms(ms: usize) {
+    assert!(
+        idt::are_interrupts_enabled(),
+        "sleep_ms called with interrupts disabled"
+    );
+    let start = ticks();
+    let duration = ms_to_ticks(ms);
+    while ticks().wrapping_sub(start) < duration {
+        unsafe {
+            asm!("hlt", options(nomem, nostack));
+        }
+    }
+}
+
+fn add_timer(ms: usize, period: Option<usize>, callback: Callback) -> Result<TimerId, TimerError> {
+    idt::without_interrupts(|| {
+        let mut timers = TIMERS.lock();
+        let idx = timers
+            .iter()
+            .position(|t| t.is_none())
+            .ok_or(TimerError::NoTimerAvailable)?;
+        let generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+        timers[idx] = Some(Timer {
+            generation,
+            deadline: ticks().wrapping_add(ms_to_ticks(ms).max(1)),
+            period,
+            callback,
+        });
+        Ok(TimerId {
+            index: idx,
+            generation,
+        })
+    })
+}
+
+/// Call `callback` once, after `ms` milliseconds
+pub fn set_timeout(ms: usize, callback: Callback) -> Result<TimerId, TimerError> {
+    add_timer(ms, None, callback)
+}
+
+/// Call `callback` every `ms` milliseconds
+pub fn set_interval(ms: usize, callback: Callback) -> Result<TimerId, TimerError> {
+    add_timer(ms, Some(ms_to_ticks(ms).max(1)), callback)
+}
+
+/// Unregister a timer
+///
+/// Fails if the timer already expired or was cancelled.
+pub fn cancel(id: TimerId) -> Result<(), TimerError> {
+    idt::without_interrupts(|| {
+        let mut timers = TIMERS.lock();
+        match timers.get_mut(id.index) {
+            Some(slot) if slot.map_or(false, |t| t.generation == id.generation) => {
+                *slot = None;
+                Ok(())
+            }
+            _ => Err(TimerError::InvalidTimer),
+        }
+    })
+}
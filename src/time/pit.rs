@@ -0,0 +1,30 @@
+//! 8253/8254 Programmable Interval Timer driver
+//!
+//! Only the channel 0, wired to IRQ0, is used.
+
+use crate::io_port::Port;
+
+/// Frequency of the PIT oscillator, in Hz
+pub const BASE_FREQUENCY: u32 = 1_193_182;
+
+/// Channel 0, lobyte/hibyte access, mode 3 (square wave), binary counter
+const CHANNEL_0_SQUARE_WAVE: u8 = 0x36;
+
+static CHANNEL_0: Port<u8> = Port::new(0x40);
+static COMMAND: Port<u8> = Port::new(0x43);
+
+/// Program the channel 0 to fire at `frequency` Hz
+///
+/// The actual frequency is rounded to the closest available divisor.
+pub fn init(frequency: u32) {
+    assert!(
+        frequency > BASE_FREQUENCY / 0x10000 && frequency <= BASE_FREQUENCY,
+        "PIT frequency out of range: {} Hz",
+        frequency
+    );
+    let divisor = (BASE_FREQUENCY + frequency / 2) / frequency;
+
+    COMMAND.write(CHANNEL_0_SQUARE_WAVE);
+    CHANNEL_0.write((divisor & 0xFF) as u8);
+    CHANNEL_0.write(((divisor >> 8) & 0xFF) as u8);
+}
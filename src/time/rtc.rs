@@ -0,0 +1,208 @@
+//! CMOS Real-Time Clock driver
+//!
+//! # Features
+//! - Read the wall-clock date, in BCD or binary mode, 12 or 24 hours format
+//! - Periodic interrupt on IRQ8, usable as a tick source
+
+use crate::idt::{self, InterruptFrame};
+use crate::io_port::Port;
+use crate::pic;
+use core::fmt;
+
+static INDEX: Port<u8> = Port::new(0x70);
+static DATA: Port<u8> = Port::new(0x71);
+
+/// Set on the index port to keep NMIs disabled while accessing the CMOS
+const NMI_DISABLE: u8 = 0x80;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_CENTURY: u8 = 0x32;
+const REG_STATUS_A: u8 = 0x0A;
+const REG_STATUS_B: u8 = 0x0B;
+const REG_STATUS_C: u8 = 0x0C;
+const REG_STATUS_D: u8 = 0x0D;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 0x80;
+const STATUS_B_24_HOURS: u8 = 0x02;
+const STATUS_B_BINARY: u8 = 0x04;
+const STATUS_B_PERIODIC_INTERRUPT: u8 = 0x40;
+const HOURS_PM: u8 = 0x80;
+
+const RTC_IRQ: u8 = 8;
+
+/// Periodic interrupt rate, giving 32768 >> (6 - 1) = 1024 Hz
+const PERIODIC_RATE: u8 = 6;
+/// Frequency of the periodic interrupt, in Hz
+pub const PERIODIC_FREQUENCY: u32 = 32768 >> (PERIODIC_RATE - 1);
+
+// The index must not be changed by an interrupt handler between the two
+// ports accesses
+fn read_register(reg: u8) -> u8 {
+    idt::without_interrupts(|| {
+        INDEX.write(NMI_DISABLE | reg);
+        let value = DATA.read();
+        enable_nmi();
+        value
+    })
+}
+
+fn write_register(reg: u8, value: u8) {
+    idt::without_interrupts(|| {
+        INDEX.write(NMI_DISABLE | reg);
+        DATA.write(value);
+        enable_nmi();
+    })
+}
+
+/// Unmask NMIs once the access is done
+///
+/// The index port can't be read back, it is left on the read only status
+/// register D.
+fn enable_nmi() {
+    INDEX.write(REG_STATUS_D);
+}
+
+fn update_in_progress() -> bool {
+    read_register(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    (value & 0x0F) + (value >> 4) * 10
+}
+
+/// A wall-clock date, as stored by the RTC
+///
+/// The RTC is assumed to be set to UTC.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+struct RawRegisters {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+impl RawRegisters {
+    fn read() -> RawRegisters {
+        while update_in_progress() {}
+        RawRegisters {
+            seconds: read_register(REG_SECONDS),
+            minutes: read_register(REG_MINUTES),
+            hours: read_register(REG_HOURS),
+            day: read_register(REG_DAY),
+            month: read_register(REG_MONTH),
+            year: read_register(REG_YEAR),
+            century: read_register(REG_CENTURY),
+        }
+    }
+}
+
+impl DateTime {
+    /// Read the current date from the RTC
+    ///
+    /// Registers are read until two consecutive reads match, so an update
+    /// happening in the middle can't produce an inconsistent date.
+    pub fn now() -> DateTime {
+        let mut raw = RawRegisters::read();
+        loop {
+            let again = RawRegisters::read();
+            if again == raw {
+                break;
+            }
+            raw = again;
+        }
+        DateTime::from_raw(raw, read_register(REG_STATUS_B))
+    }
+
+    fn from_raw(raw: RawRegisters, status_b: u8) -> DateTime {
+        let is_pm = raw.hours & HOURS_PM != 0;
+        let convert = |v: u8| match status_b & STATUS_B_BINARY {
+            0 => bcd_to_binary(v),
+            _ => v,
+        };
+
+        let mut hours = convert(raw.hours & !HOURS_PM);
+        if status_b & STATUS_B_24_HOURS == 0 {
+            hours = match (hours, is_pm) {
+                (12, false) => 0,
+                (12, true) => 12,
+                (h, true) => h + 12,
+                (h, false) => h,
+            };
+        }
+
+        // The century register is not guaranteed to exist
+        let century = match convert(raw.century) {
+            c if c >= 19 && c <= 99 => c as u16,
+            _ => 20,
+        };
+
+        DateTime {
+            year: century * 100 + convert(raw.year) as u16,
+            month: convert(raw.month),
+            day: convert(raw.day),
+            hours,
+            minutes: convert(raw.minutes),
+            seconds: convert(raw.seconds),
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+            self.year, self.month, self.day, self.hours, self.minutes, self.seconds
+        )
+    }
+}
+
+/// Enable the RTC periodic interrupt
+///
+/// `handler` is called `PERIODIC_FREQUENCY` times per second.
+pub fn enable_periodic_interrupt(handler: fn()) {
+    idt::without_interrupts(|| {
+        PERIODIC_HANDLER.lock().replace(handler);
+
+        let status_a = read_register(REG_STATUS_A);
+        write_register(REG_STATUS_A, (status_a & 0xF0) | PERIODIC_RATE);
+        let status_b = read_register(REG_STATUS_B);
+        write_register(REG_STATUS_B, status_b | STATUS_B_PERIODIC_INTERRUPT);
+        // Flush a possibly pending interrupt
+        read_register(REG_STATUS_C);
+
+        idt::register_handler(pic::vector(RTC_IRQ), rtc_interrupt_handler);
+        pic::unmask(RTC_IRQ);
+    });
+}
+
+use spin::Mutex;
+
+static PERIODIC_HANDLER: Mutex<Option<fn()>> = Mutex::new(None);
+
+fn rtc_interrupt_handler(_frame: &mut InterruptFrame) {
+    // The RTC won't raise another interrupt until status C is read
+    read_register(REG_STATUS_C);
+    let handler = *PERIODIC_HANDLER.lock();
+    if let Some(handler) = handler {
+        handler();
+    }
+}
@@ -0,0 +1,352 @@
+//! Ring 3 execution
+//!
+//! Run a program with user privileges, in its own process. Interrupts
+//! occuring in ring 3 are handled on the process kernel stack, provided to the
+//! CPU through the TSS.
+//!
+//! Programs are either ELF32 executables, or flat position independent
+//! binaries. They start with the System V initial stack: argc, the argv and
+//! envp arrays, then the auxiliary vector.
+
+use crate::dynamic_memory_management::Heap;
+use crate::elf::{self, Elf, ElfError};
+use crate::idt::{self, InterruptFrame};
+use crate::initrd;
+use crate::physical_memory_management::{frame_manager, PhysicalMemoryError, PAGE_SIZE_4K};
+use crate::process::{self, Pid};
+use crate::virtual_memory_management::{
+    self as vmm, current_directory, PageFlags, VirtualMemoryError,
+};
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::slice;
+
+/// Virtual address where user programs are loaded
+pub const USER_CODE_ADDRESS: usize = 0x40000000;
+/// Start of the user heap, grown with the sbrk system call
+pub const USER_HEAP_START: usize = 0x50000000;
+/// Top of the user stack, right below the kernel space
+pub const USER_STACK_TOP: usize = vmm::USER_SPACE_END;
+/// Demand paged, only the pages in use are backed
+const USER_STACK_PAGES: usize = 256;
+
+/// Auxiliary vector entry types
+const AT_NULL: usize = 0;
+const AT_PHDR: usize = 3;
+const AT_PHENT: usize = 4;
+const AT_PHNUM: usize = 5;
+const AT_PAGESZ: usize = 6;
+const AT_ENTRY: usize = 9;
+
+extern "C" {
+    static user_fault_start: u8;
+    static user_fault_end: u8;
+    static user_hello_start: u8;
+    static user_hello_end: u8;
+    static user_family_start: u8;
+    static user_family_end: u8;
+}
+
+#[derive(Debug)]
+pub enum UserModeError {
+    PagingDisabled,
+    PhysicalMemoryError(PhysicalMemoryError),
+    VirtualMemoryError(VirtualMemoryError),
+    ElfError(ElfError),
+    /// A segment is out of the space reserved for programs
+    BadSegmentAddress(usize),
+    /// The entry point is out of every executable segment
+    BadEntryPoint(usize),
+    /// The arguments and environment don't fit in the stack
+    ArgumentsTooLong,
+}
+
+/// Map fresh, zero filled, user pages in the current address space
+fn map_user_pages(start: usize, n_pages: usize) -> Result<(), UserModeError> {
+    for i in 0..n_pages {
+        let page = start + i * PAGE_SIZE_4K;
+        let frame = frame_manager(|bitmap| bitmap.alloc_frame())
+            .map_err(UserModeError::PhysicalMemoryError)?;
+        current_directory(|directory| directory.map_pages(frame, page, PageFlags::USER_DATA))
+            .map_err(|e| {
+                frame_manager(|bitmap| bitmap.free_frame(frame)).ok();
+                UserModeError::VirtualMemoryError(e)
+            })?;
+        unsafe {
+            core::ptr::write_bytes(page as *mut u8, 0, PAGE_SIZE_4K);
+        }
+    }
+    Ok(())
+}
+
+/// Copy a flat binary at `USER_CODE_ADDRESS`
+fn load_flat(program: &[u8]) -> Result<(), UserModeError> {
+    let code_pages = (program.len() + PAGE_SIZE_4K - 1) / PAGE_SIZE_4K;
+    map_user_pages(USER_CODE_ADDRESS, code_pages)?;
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            program.as_ptr(),
+            USER_CODE_ADDRESS as *mut u8,
+            program.len(),
+        );
+    }
+    Ok(())
+}
+
+/// Map the segments of an executable
+///
+/// Segments must lie between `USER_SPACE_START` and `USER_HEAP_START`, the
+/// entry point in an executable one. Pages are mapped writable to be filled,
+/// then get the flags of their segments.
+fn load_elf(elf: &Elf) -> Result<(), UserModeError> {
+    let entry = elf.entry();
+    if !elf.segments().any(|segment| {
+        segment.executable
+            && segment.virtual_address <= entry
+            && entry < segment.virtual_address + segment.memory_size
+    }) {
+        return Err(UserModeError::BadEntryPoint(entry));
+    }
+
+    // Pages loaded so far, with their final flags
+    let mut pages: Vec<(usize, PageFlags)> = Vec::new();
+
+    for segment in elf.segments() {
+        let start = segment.virtual_address;
+        let end = start + segment.memory_size;
+        if start < vmm::USER_SPACE_START || end > USER_HEAP_START {
+            return Err(UserModeError::BadSegmentAddress(start));
+        }
+        let mut flags = PageFlags::PRESENT | PageFlags::USER;
+        flags.set(PageFlags::WRITABLE, segment.writable);
+        flags.set(PageFlags::NO_EXECUTE, !segment.executable);
+
+        let mut page = start & !0xFFF;
+        while page < end {
+            match pages.iter_mut().find(|(p, _)| *p == page) {
+                // Shared with the previous segment, executable if one is
+                Some((_, page_flags)) => {
+                    let no_execute = *page_flags & flags & PageFlags::NO_EXECUTE;
+                    *page_flags = (*page_flags | flags) & !PageFlags::NO_EXECUTE | no_execute;
+                }
+                None => {
+                    map_user_pages(page, 1)?;
+                    pages.push((page, flags));
+                }
+            }
+            page += PAGE_SIZE_4K;
+        }
+        // The rest of the segment, its .bss, is already zero filled
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                segment.data.as_ptr(),
+                start as *mut u8,
+                segment.data.len(),
+            );
+        }
+    }
+
+    current_directory(|directory| {
+        for &(page, flags) in pages.iter().filter(|(_, f)| *f != PageFlags::USER_DATA) {
+            directory
+                .protect(page, flags)
+                .map_err(UserModeError::VirtualMemoryError)?;
+        }
+        Ok(())
+    })
+}
+
+/// Write the initial stack of a program, ending at `USER_STACK_TOP`
+///
+/// From the returned stack pointer: argc, the argv pointers, NULL, the envp
+/// pointers, NULL, then the auxiliary vector pairs ending with `AT_NULL`. The
+/// strings are stored above.
+fn setup_stack(
+    argv: &[&str],
+    envp: &[&str],
+    auxv: &[(usize, usize)],
+) -> Result<usize, UserModeError> {
+    let strings_size: usize = argv.iter().chain(envp).map(|s| s.len() + 1).sum();
+    let n_words = 1 + argv.len() + 1 + envp.len() + 1 + 2 * (auxv.len() + 1);
+    // Keep at least half of the stack to the program
+    if strings_size + n_words * size_of::<u32>() + 16 > USER_STACK_PAGES * PAGE_SIZE_4K / 2 {
+        return Err(UserModeError::ArgumentsTooLong);
+    }
+
+    let mut sp = USER_STACK_TOP;
+    let mut push_string = |s: &str| -> usize {
+        sp -= s.len() + 1;
+        unsafe {
+            core::ptr::copy_nonoverlapping(s.as_ptr(), sp as *mut u8, s.len());
+            *((sp + s.len()) as *mut u8) = 0;
+        }
+        sp
+    };
+    let argv_pointers: Vec<usize> = argv.iter().map(|s| push_string(s)).collect();
+    let envp_pointers: Vec<usize> = envp.iter().map(|s| push_string(s)).collect();
+
+    let mut words: Vec<usize> = Vec::with_capacity(n_words);
+    words.push(argv.len());
+    words.extend(argv_pointers);
+    words.push(0);
+    words.extend(envp_pointers);
+    words.push(0);
+    for &(typ, value) in auxv.iter().chain(&[(AT_NULL, 0)]) {
+        words.push(typ);
+        words.push(value);
+    }
+
+    let sp = (sp - n_words * size_of::<u32>()) & !0xF;
+    let stack = unsafe { slice::from_raw_parts_mut(sp as *mut u32, n_words) };
+    for (slot, &word) in stack.iter_mut().zip(&words) {
+        *slot = word as u32;
+    }
+    Ok(sp)
+}
+
+/// Load a program and its stack in the current address space
+///
+/// Return its entry point and initial stack pointer.
+fn load(image: &[u8], argv: &[&str], envp: &[&str]) -> Result<(usize, usize), UserModeError> {
+    let mut auxv = Vec::new();
+    auxv.push((AT_PAGESZ, PAGE_SIZE_4K));
+    let entry = if elf::is_elf(image) {
+        let elf = Elf::parse(image).map_err(UserModeError::ElfError)?;
+        load_elf(&elf)?;
+        if let Some(address) = elf.program_headers_address() {
+            let (count, size) = elf.program_headers_layout();
+            auxv.push((AT_PHDR, address));
+            auxv.push((AT_PHENT, size));
+            auxv.push((AT_PHNUM, count));
+        }
+        auxv.push((AT_ENTRY, elf.entry()));
+        elf.entry()
+    } else {
+        load_flat(image)?;
+        USER_CODE_ADDRESS
+    };
+
+    vmm::reserve(
+        USER_STACK_TOP - USER_STACK_PAGES * PAGE_SIZE_4K,
+        USER_STACK_TOP,
+        PageFlags::USER_DATA | PageFlags::NO_EXECUTE,
+    )
+    .map_err(UserModeError::VirtualMemoryError)?;
+    let stack_pointer = setup_stack(argv, envp, &auxv)?;
+    Ok((entry, stack_pointer))
+}
+
+/// Load a program in the address space `page_directory`
+///
+/// Return its entry point and initial stack pointer.
+fn load_into(
+    page_directory: usize,
+    image: &[u8],
+    argv: &[&str],
+    envp: &[&str],
+) -> Result<(usize, usize), UserModeError> {
+    // No context switch may happen while we borrow the address space
+    idt::without_interrupts(|| {
+        let previous = vmm::current_address_space();
+        vmm::switch_address_space(page_directory);
+        let loaded = load(image, argv, envp);
+        vmm::switch_address_space(previous);
+        loaded
+    })
+}
+
+/// Start a program in a new process
+///
+/// `image` is an ELF executable, or a flat position independent binary copied
+/// at `USER_CODE_ADDRESS`. It is given a stack ending at `USER_STACK_TOP`,
+/// holding `argv` and `envp`.
+/// The process is scheduled along the other tasks, return its pid.
+pub fn spawn(name: &str, image: &[u8], argv: &[&str], envp: &[&str]) -> Result<Pid, UserModeError> {
+    assert!(!image.is_empty(), "cannot run an empty program");
+    if !current_directory(|directory| directory.is_enabled()) {
+        return Err(UserModeError::PagingDisabled);
+    }
+
+    let pid = process::table(|t| t.spawn_user(name)).map_err(UserModeError::VirtualMemoryError)?;
+    let page_directory = process::table(|t| t.get(pid).unwrap().page_directory());
+    let loaded = load_into(page_directory, image, argv, envp);
+
+    process::table(|t| match loaded {
+        Ok((entry, stack_pointer)) => {
+            t.get_mut(pid).unwrap().start_user(entry, stack_pointer);
+            Ok(pid)
+        }
+        Err(e) => {
+            t.remove(pid);
+            Err(e)
+        }
+    })
+}
+
+/// Replace the program of the current process
+///
+/// The process gets a fresh address space holding `image`, and `frame` is
+/// set to start it. Nothing changes if the program can't be loaded.
+pub fn exec(
+    frame: &mut InterruptFrame,
+    name: &str,
+    image: &[u8],
+    argv: &[&str],
+    envp: &[&str],
+) -> Result<(), UserModeError> {
+    assert!(!image.is_empty(), "cannot run an empty program");
+    let page_directory = vmm::create_address_space().map_err(UserModeError::VirtualMemoryError)?;
+
+    let (entry, stack_pointer) = match load_into(page_directory, image, argv, envp) {
+        Ok(start) => start,
+        Err(e) => {
+            vmm::destroy_address_space(page_directory).unwrap();
+            return Err(e);
+        }
+    };
+
+    let heap = unsafe { Heap::new(USER_HEAP_START as *const usize, false) };
+    process::table(|t| {
+        t.current_mut()
+            .unwrap()
+            .replace_image(name, page_directory, heap)
+    })
+    .map_err(UserModeError::VirtualMemoryError)?;
+    *frame = process::user_frame(entry, stack_pointer);
+    Ok(())
+}
+
+/// Run a program in a new process and wait for its termination
+///
+/// Return the process exit status.
+pub fn run(name: &str, image: &[u8], argv: &[&str], envp: &[&str]) -> Result<usize, UserModeError> {
+    let pid = spawn(name, image, argv, envp)?;
+    Ok(process::wait(pid).unwrap())
+}
+
+unsafe fn program_slice(start: &'static u8, end: &'static u8) -> &'static [u8] {
+    let start = start as *const u8;
+    slice::from_raw_parts(start, end as *const u8 as usize - start as usize)
+}
+
+/// Get a program embedded in the kernel image
+///
+/// # Programs
+/// - fault: spin for a while, then execute a privileged instruction
+/// - hello: greet the user, echo a line typed on the keyboard and exit
+/// - family: fork a child executing hello, and exit with its status
+pub fn builtin_program(name: &str) -> Option<&'static [u8]> {
+    unsafe {
+        match name {
+            "fault" => Some(program_slice(&user_fault_start, &user_fault_end)),
+            "hello" => Some(program_slice(&user_hello_start, &user_hello_end)),
+            "family" => Some(program_slice(&user_family_start, &user_family_end)),
+            _ => None,
+        }
+    }
+}
+
+/// Find a program by name, among the built-in ones then in the initrd
+pub fn find_program(name: &str) -> Option<&'static [u8]> {
+    builtin_program(name).or_else(|| initrd::get(name))
+}
@@ -0,0 +1,100 @@
+//! Copy on write
+//!
+//! Address spaces share frames instead of copying them. A shared page that was
+//! writable is mapped read only with the `COPY_ON_WRITE` flag, the first
+//! write to it faults and the writer gets its own copy.
+//!
+//! Frames count their references, a frame is only freed by its last user.
+
+use super::{
+    current_address_space, current_directory, switch_address_space, PageFlags, VirtualMemoryError,
+    TEMPORARY_PAGE,
+};
+use crate::idt;
+use crate::physical_memory_management::{frame_manager, PAGE_SIZE_4K};
+
+/// Present, writable, user and no execute flags, kept by the copies
+pub(super) const ACCESS_FLAGS: PageFlags = PageFlags::USER_DATA.union(PageFlags::NO_EXECUTE);
+
+/// Map the page of the current address space in `child` too, at the same
+/// address and on the same frame
+///
+/// A writable page becomes copy on write in both spaces. Return false if the
+/// frame can't be shared anymore, the page must then be copied.
+pub fn share_page(page: usize, child: usize) -> Result<bool, VirtualMemoryError> {
+    idt::without_interrupts(|| {
+        let (frame, flags) = match current_directory(|directory| {
+            (
+                directory.get_page_frame(page),
+                directory.get_page_flags(page),
+            )
+        }) {
+            (Some(frame), Some(flags)) => {
+                (frame, flags & (ACCESS_FLAGS | PageFlags::COPY_ON_WRITE))
+            }
+            _ => return Ok(true),
+        };
+        if frame_manager(|bitmap| bitmap.share_frame(frame)).is_err() {
+            return Ok(false);
+        }
+        let flags = match flags.contains(PageFlags::WRITABLE) {
+            true => flags & !PageFlags::WRITABLE | PageFlags::COPY_ON_WRITE,
+            false => flags,
+        };
+        current_directory(|directory| directory.protect(page, flags))?;
+
+        let parent = current_address_space();
+        switch_address_space(child);
+        let mapped = current_directory(|directory| directory.map_pages(frame, page, flags));
+        switch_address_space(parent);
+        mapped.map_err(|e| {
+            frame_manager(|bitmap| bitmap.free_frame(frame)).ok();
+            e
+        })?;
+        Ok(true)
+    })
+}
+
+/// Resolve a write to a present page
+///
+/// A copy on write page gets a private copy of its frame, or is simply made
+/// writable if it is not shared anymore. Return false if the page is not copy
+/// on write, or no frame is left: the fault is a real one.
+pub fn handle_write_fault(address: usize) -> bool {
+    let page = address & !0xFFF;
+
+    current_directory(|directory| {
+        let flags = match directory.get_page_flags(page) {
+            Some(flags) if flags.contains(PageFlags::COPY_ON_WRITE) => flags,
+            _ => return false,
+        };
+        let frame = directory.get_page_frame(page).unwrap();
+        let flags = flags & ACCESS_FLAGS | PageFlags::WRITABLE;
+
+        if frame_manager(|bitmap| bitmap.reference_count(frame)) == 1 {
+            return directory.protect(page, flags).is_ok();
+        }
+
+        let copy = match frame_manager(|bitmap| bitmap.alloc_frame()) {
+            Ok(copy) => copy,
+            Err(_) => return false,
+        };
+        // The kernel space table of the temporary page always exists
+        directory
+            .map_pages(copy, TEMPORARY_PAGE, PageFlags::KERNEL_DATA)
+            .unwrap();
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                page as *const u8,
+                TEMPORARY_PAGE as *mut u8,
+                PAGE_SIZE_4K,
+            );
+        }
+        directory.unlink_page(TEMPORARY_PAGE);
+        directory.unlink_page(page);
+        directory.map_pages(copy, page, flags).unwrap();
+        // This space reference to the shared frame
+        frame_manager(|bitmap| bitmap.free_frame(frame)).unwrap();
+        true
+    })
+}
@@ -0,0 +1,180 @@
+//! Demand paging
+//!
+//! Regions of virtual memory are reserved without any frame. A page of a
+//! region is backed by a zero filled frame the first time it is accessed, when
+//! the page fault handler calls `handle_fault`.
+//!
+//! User regions belong to the address space they were reserved in, kernel
+//! regions are shared by all of them.
+
+use super::{current_address_space, current_directory, PageFlags, VirtualMemoryError, KERNEL_BASE};
+use crate::idt;
+use crate::physical_memory_management::{frame_manager, PAGE_SIZE_4K};
+use spin::Mutex;
+
+/// Regions are kept out of the heap, which is itself demand paged
+const MAX_REGIONS: usize = 256;
+
+#[derive(Debug, Copy, Clone)]
+struct Region {
+    /// Page directory of a user region, `None` for a kernel one
+    address_space: Option<usize>,
+    start: usize,
+    end: usize,
+    /// Flags of the pages backing the region
+    flags: PageFlags,
+}
+
+impl Region {
+    fn contains(&self, address: usize) -> bool {
+        self.start <= address && address < self.end
+    }
+
+    /// Check that the region pages can be accessed that way
+    fn allows(&self, write: bool, user: bool) -> bool {
+        (!write || self.flags.contains(PageFlags::WRITABLE))
+            && (!user || self.flags.contains(PageFlags::USER))
+    }
+}
+
+static REGIONS: Mutex<[Option<Region>; MAX_REGIONS]> = Mutex::new([None; MAX_REGIONS]);
+
+/// Access the regions, with interrupts disabled, the page fault handler needs
+/// them
+fn regions<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut [Option<Region>; MAX_REGIONS]) -> R,
+{
+    idt::without_interrupts(|| f(&mut REGIONS.lock()))
+}
+
+/// Address space a region holding `address` belongs to
+fn owner(address: usize) -> Option<usize> {
+    match address >= KERNEL_BASE {
+        true => None,
+        false => Some(current_address_space()),
+    }
+}
+
+/// Reserve `start..end`, to be backed on access by pages with `flags`
+///
+/// A region already starting at `start` in the same address space is resized
+/// instead, which lets heaps grow and shrink.
+pub fn reserve(start: usize, end: usize, flags: PageFlags) -> Result<(), VirtualMemoryError> {
+    assert_eq!(
+        0,
+        start & 0xFFF,
+        "region is not 4k aligned: {:#010x}",
+        start
+    );
+    let address_space = owner(start);
+
+    regions(|regions| {
+        if let Some(region) = regions
+            .iter_mut()
+            .flatten()
+            .find(|r| r.address_space == address_space && r.start == start)
+        {
+            region.end = end;
+            region.flags = flags;
+            return Ok(());
+        }
+        let slot = regions
+            .iter_mut()
+            .find(|r| r.is_none())
+            .ok_or(VirtualMemoryError::TooManyRegions)?;
+        *slot = Some(Region {
+            address_space,
+            start,
+            end,
+            flags,
+        });
+        Ok(())
+    })
+}
+
+/// Free the frames backing the pages of `start..end` in the current address
+/// space
+///
+/// Pages never accessed have no frame to free.
+pub fn release(start: usize, end: usize) -> Result<(), VirtualMemoryError> {
+    let mut page = start & !0xFFF;
+    while page < end {
+        current_directory(|directory| match directory.get_page_flags(page) {
+            Some(_) => directory.unmap_pages(page),
+            None => Ok(()),
+        })?;
+        page += PAGE_SIZE_4K;
+    }
+    Ok(())
+}
+
+/// Back the page containing `address`, if a region allows the access
+///
+/// Return false when `address` is out of every region, the region forbids
+/// the access, or no frame is left: the fault is a real one.
+pub fn handle_fault(address: usize, write: bool, user: bool) -> bool {
+    let address_space = owner(address);
+
+    idt::without_interrupts(|| {
+        let region = regions(|regions| {
+            regions
+                .iter()
+                .flatten()
+                .find(|r| r.address_space == address_space && r.contains(address))
+                .copied()
+        });
+        match region {
+            Some(region) if region.allows(write, user) => {
+                back_page(address & !0xFFF, region.flags).is_ok()
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Map a zero filled frame at `page`
+fn back_page(page: usize, flags: PageFlags) -> Result<(), VirtualMemoryError> {
+    let frame = frame_manager(|bitmap| bitmap.alloc_frame())
+        .map_err(VirtualMemoryError::PhysicalMemoryError)?;
+    current_directory(|directory| directory.map_pages(frame, page, flags)).map_err(|e| {
+        frame_manager(|bitmap| bitmap.free_frame(frame)).ok();
+        e
+    })?;
+    unsafe {
+        core::ptr::write_bytes(page as *mut u8, 0, PAGE_SIZE_4K);
+    }
+    Ok(())
+}
+
+/// Give the address space `child` the user regions of `parent`
+pub fn clone_regions(parent: usize, child: usize) -> Result<(), VirtualMemoryError> {
+    regions(|regions| {
+        for i in 0..MAX_REGIONS {
+            let region = match regions[i] {
+                Some(region) if region.address_space == Some(parent) => region,
+                _ => continue,
+            };
+            let slot = regions
+                .iter_mut()
+                .find(|r| r.is_none())
+                .ok_or(VirtualMemoryError::TooManyRegions)?;
+            *slot = Some(Region {
+                address_space: Some(child),
+                ..region
+            });
+        }
+        Ok(())
+    })
+}
+
+/// Forget the user regions of a destroyed address space
+pub fn drop_regions(address_space: usize) {
+    regions(|regions| {
+        for region in regions.iter_mut() {
+            if region.map_or(false, |r| r.address_space == Some(address_space)) {
+                *region = None;
+            }
+        }
+    });
+}
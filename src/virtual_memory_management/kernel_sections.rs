@@ -0,0 +1,100 @@
+//! Protection of the kernel image
+//!
+//! With PAE, the pages of the kernel image are mapped one by one with the
+//! rights of their section: the code is read only, the data is not
+//! executable. The boot stack lives in `.bss`. The rest of the memory mapped
+//! at boot is not executable either.
+
+use core::ops::Range;
+
+use super::{kernel_to_physical, PageDirectory, PageFlags, VirtualMemoryError};
+use crate::external_symbols::{
+    get_kernel_end, get_kernel_start, get_section_bss_end, get_section_bss_start,
+    get_section_data_end, get_section_data_start, get_section_rodata_end, get_section_rodata_start,
+    get_section_text_end, get_section_text_start, get_stack_high, get_stack_low,
+};
+use crate::physical_memory_management::{PhysicalAddress, PAGE_SIZE_4K};
+
+/// Flags of the kernel memory holding neither code nor constants
+pub const KERNEL_MEMORY_FLAGS: PageFlags = PageFlags::KERNEL_DATA.union(PageFlags::NO_EXECUTE);
+
+struct Section {
+    name: &'static str,
+    /// Page aligned
+    pages: Range<usize>,
+    flags: PageFlags,
+}
+
+impl Section {
+    fn new(
+        name: &'static str,
+        start: *const usize,
+        end: *const usize,
+        flags: PageFlags,
+    ) -> Section {
+        Section {
+            name,
+            pages: start as usize & !0xFFF..(end as usize + 0xFFF) & !0xFFF,
+            flags,
+        }
+    }
+}
+
+fn sections() -> [Section; 4] {
+    [
+        Section::new(
+            ".text",
+            get_section_text_start(),
+            get_section_text_end(),
+            PageFlags::PRESENT,
+        ),
+        Section::new(
+            ".rodata",
+            get_section_rodata_start(),
+            get_section_rodata_end(),
+            PageFlags::PRESENT | PageFlags::NO_EXECUTE,
+        ),
+        Section::new(
+            ".data",
+            get_section_data_start(),
+            get_section_data_end(),
+            KERNEL_MEMORY_FLAGS,
+        ),
+        Section::new(
+            ".bss",
+            get_section_bss_start(),
+            get_section_bss_end(),
+            KERNEL_MEMORY_FLAGS,
+        ),
+    ]
+}
+
+/// Name of the kernel section holding `address`, the boot stack apart
+pub fn section_name(address: usize) -> Option<&'static str> {
+    if (get_stack_low() as usize..get_stack_high() as usize).contains(&address) {
+        return Some("stack");
+    }
+    sections()
+        .iter()
+        .find(|section| section.pages.contains(&address))
+        .map(|section| section.name)
+}
+
+/// Some page of `range` holds the kernel image
+pub fn overlaps(range: &Range<usize>) -> bool {
+    range.start < get_kernel_end() as usize && (get_kernel_start() as usize) < range.end
+}
+
+/// Map the pages of `range` on the frames `KERNEL_BASE` below, with the
+/// rights of their section
+pub fn map(directory: &mut PageDirectory, range: Range<usize>) -> Result<(), VirtualMemoryError> {
+    let sections = sections();
+    for page in range.step_by(PAGE_SIZE_4K) {
+        let flags = sections
+            .iter()
+            .find(|section| section.pages.contains(&page))
+            .map_or(KERNEL_MEMORY_FLAGS, |section| section.flags);
+        directory.map_pages(kernel_to_physical(page) as PhysicalAddress, page, flags)?;
+    }
+    Ok(())
+}
@@ -1,133 +1,659 @@
 //! Paging management
 //!
-//! Keep track of an unique page directory. Dynamicaly manage page tables.
+//! Keep track of the current page directory. Dynamicaly manage page tables.
+//! Create and switch between address spaces sharing the same kernel space.
+//!
+//! The kernel lives in the higher half: physical memory it uses is mapped
+//! `KERNEL_BASE` higher. The boot code runs it on a bootstrap directory
+//! mapping the first 16 MiB, replaced here by a directory only mapping what
+//! the kernel uses.
 
+mod copy_on_write;
+mod demand_paging;
+mod kernel_sections;
+mod page_flags;
 mod page_structs;
+mod paging_mode;
 
-use self::page_structs::PageDirectory;
-use crate::external_symbols::{get_kernel_end, get_kernel_start};
-use crate::physical_memory_management::{BITMAP, PAGE_SIZE_4K};
+pub use self::copy_on_write::handle_write_fault;
+pub use self::demand_paging::{handle_fault, release, reserve};
+pub use self::kernel_sections::{section_name, KERNEL_MEMORY_FLAGS};
+pub use self::page_flags::PageFlags;
+pub use self::page_structs::{
+    MappedRegion, MappedRegions, PageDirectory, PageDirectoryPointerTable, PageTable,
+    VirtualMemoryError,
+};
+use self::paging_mode::{
+    directory_index, large_page_size, page_address, recursive_entries, table_len,
+};
+pub use self::paging_mode::{has_no_execute, is_pae, PagingMode};
+use crate::external_symbols::{
+    get_first_large_page_after_kernel, get_kernel_end, get_kernel_start,
+};
+use crate::idt;
+use crate::physical_memory_management::{
+    frame_manager, FrameManager, PhysicalAddress, PAGE_SIZE_4K,
+};
 use crate::MultibootInfo;
-use core::convert::TryInto;
-use core::ptr::Unique;
+use alloc::vec;
+use core::iter::once;
+use core::ops::Range;
+
+/// Physical address of the kernel page directory frame
+///
+/// With PAE, the first of the four contiguous page directories.
+pub const PAGE_DIR_ADDRESS: usize = 0x21000;
+/// Physical address of the kernel page directory pointer table, with PAE
+pub const PDPT_ADDRESS: usize = 0x25000;
+
+/// Offset between the kernel virtual addresses and the physical ones
+///
+/// Everything above belongs to the kernel space, mapped the same way in every
+/// address space.
+pub const KERNEL_BASE: usize = 0xC0000000;
+/// Physical memory mapped by the bootstrap page directory
+const BOOT_MAPPING_END: usize = 0x1000000;
+/// Physical memory reachable by 32 bit paging
+const LEGACY_MEMORY_END: PhysicalAddress = 0x1_0000_0000;
 
-/// Physical address of the page directory frame
-const PAGE_DIR_ADDRESS: usize = 0x21000;
+/// First address of the user space
+///
+/// The first page is never mapped, to catch null pointers.
+pub const USER_SPACE_START: usize = 0x1000;
+/// End of the user space
+pub const USER_SPACE_END: usize = KERNEL_BASE;
+
+/// Kernel pages holding the frame manager structures, once paging is enabled
+const FRAME_MANAGER_WINDOW: usize = 0xE0000000;
+
+/// Kernel page used to reach frames that are not mapped anywhere
+///
+/// Above lives the self referencing directory.
+const TEMPORARY_PAGE: usize = 0xFF7FF000;
+/// Kernel pages used to build the page directories of a new address space,
+/// one per directory
+const TEMPORARY_DIRECTORY: usize = 0xFF7FB000;
 
+/// Kernel address of a physical address mapped by the kernel
+///
+/// Only valid for the frames the kernel maps at boot: low memory, the kernel
+/// image and the boot information.
+pub const fn physical_to_kernel(address: usize) -> usize {
+    address + KERNEL_BASE
+}
+
+/// Physical address of a kernel address mapped at boot
+pub const fn kernel_to_physical(address: usize) -> usize {
+    address - KERNEL_BASE
+}
+
+/// Load a page directory and make sure paging is enabled
+///
+/// Read only pages are also protected from the kernel, for copy on write.
+/// 4 MiB pages are enabled with the page size extension.
 fn enable(page_dir_address: usize) {
     unsafe {
-        asm!("mov cr3, eax
+        asm!("mov ebx, cr4
+            or ebx, 0x10
+            mov cr4, ebx
+            mov cr3, eax
             mov ebx, cr0
-            or ebx, 0x80000000
+            or ebx, 0x80010000
             mov cr0, ebx",
             in("eax") page_dir_address, out("ebx") _,
             options(nostack));
     }
 }
 
+/// Switch from 32 bit paging to PAE paging, loading a page directory pointer
+/// table
+///
+/// Paging must be disabled to change mode: the switch runs at the physical
+/// address of the code, which must be identity mapped by both the current
+/// directory and the new one.
+fn enable_pae(pdpt_address: usize) {
+    unsafe {
+        asm!("lea ecx, [2f]
+            sub ecx, edx
+            jmp ecx
+            2:
+            mov ecx, cr0
+            and ecx, 0x7FFFFFFF
+            mov cr0, ecx
+            mov ecx, cr4
+            or ecx, 0x30
+            mov cr4, ecx
+            mov cr3, eax
+            mov ecx, cr0
+            or ecx, 0x80010000
+            mov cr0, ecx
+            lea ecx, [3f]
+            jmp ecx
+            3:",
+            in("eax") pdpt_address, in("edx") KERNEL_BASE, out("ecx") _,
+            options(nostack));
+    }
+}
+
+/// Let the no execute flag of PAE entries be enforced, in the EFER register
+fn enable_no_execute() {
+    unsafe {
+        asm!("rdmsr
+            or eax, 0x800
+            wrmsr",
+            in("ecx") 0xC0000080u32, out("eax") _, out("edx") _,
+            options(nomem, nostack));
+    }
+}
+
 /// Setup memory
 ///
-/// If arg is false, will tag specific area of memory as used in BITMAP, to avoid latter override.
-/// Else if arg is true it will also identity page those memory area, set up the recursive page directory trick on the last entry
-/// of the page directory and enable paging.
+/// With paging `Disabled`, will tag specific area of memory as used in BITMAP, to avoid latter
+/// override. The kernel keeps running on the bootstrap directory, only reaching the first 16 MiB.
+/// Else it will also map those memory area at `KERNEL_BASE` above their physical address, set up
+/// the recursive page directory trick on the last entries of the page directory and switch to it.
+/// The user space is left empty.
 ///
 /// Target memory areas:
 /// - Global Descriptor Table
 /// - Ps2 ports
 /// - VGA screen memory map
 /// - The whole kernel
-pub fn init(enable_paging: bool, multiboot_info: MultibootInfo) {
+/// - The boot modules, only reserved
+/// - The frame manager structures, in the first free RAM
+///
+/// The low memory up to the end of the kernel is mapped with large pages,
+/// covering the first four areas. Frames left free in there stay available.
+/// With PAE, the large pages holding the kernel image are split to protect
+/// its sections, and nothing else is executable.
+///
+/// PAE paging falls back to 32 bit paging if the CPU lacks it. Without PAE,
+/// the RAM above 4 GiB is left unused.
+pub fn init(mode: PagingMode, multiboot_info: MultibootInfo) {
+    let mode = match mode {
+        PagingMode::Pae if !paging_mode::pae_supported() => {
+            warn!("PAE is not supported, falling back to 32 bit paging");
+            PagingMode::Legacy
+        }
+        mode => mode,
+    };
+    if mode == PagingMode::Pae {
+        paging_mode::select_pae();
+    }
+
     // Only let available the RAM really provided by the system
     let mem_map = multiboot_info.get_memory_map().unwrap();
-    unsafe {
-        assert_eq!(
-            (*mem_map.inner).entry_version,
-            0,
-            "multiboot memory doesn't use version 0 entries"
-        );
-        for mem_entry in mem_map {
-            if (*mem_entry).typ != 1 {
-                let mut c_mem = 0;
-                let base_frame = (*mem_entry).base_addr & !0xFFF;
-                let end = (*mem_entry).base_addr + (*mem_entry).length;
-
-                while base_frame + c_mem < end {
-                    BITMAP
-                        .lock()
-                        .alloc_frame_by_address((base_frame + c_mem).try_into().unwrap())
-                        .unwrap();
-                    c_mem += PAGE_SIZE_4K as u64;
-                }
+    assert_eq!(
+        mem_map.entry_version(),
+        0,
+        "multiboot memory doesn't use version 0 entries"
+    );
+    let memory_end = mem_map
+        .entries()
+        .filter(|entry| entry.typ == 1)
+        .map(|entry| entry.base_addr + entry.length)
+        .max()
+        .unwrap_or(0);
+    let limit = match is_pae() {
+        true => memory_end,
+        false => memory_end.min(LEGACY_MEMORY_END),
+    };
+    let storage_size = FrameManager::storage_size(limit);
+    let storage = find_storage(multiboot_info, storage_size as PhysicalAddress)
+        .expect("no free memory to track the physical frames");
+    frame_manager(|bitmap| {
+        unsafe { bitmap.reset(limit, physical_to_kernel(storage as usize)) };
+        for mem_entry in mem_map.entries().filter(|entry| entry.typ == 1) {
+            bitmap.release_range(mem_entry.base_addr, mem_entry.base_addr + mem_entry.length);
+        }
+        // Regions may overlap, the reserved ones win
+        for mem_entry in mem_map.entries().filter(|entry| entry.typ != 1) {
+            let mut frame = mem_entry.base_addr & !0xFFF;
+            let end = (mem_entry.base_addr + mem_entry.length).min(limit);
+            while frame < end {
+                bitmap.alloc_frame_by_address(frame).ok();
+                frame += PAGE_SIZE_4K as PhysicalAddress;
             }
         }
-    }
 
-    let kernel_first_page = get_kernel_start() as usize & !0xFFF;
-    let kernel_last_page = get_kernel_end() as usize & !0xFFF;
-    let multiboot_frame_add = multiboot_info.inner as usize & !0xFFF;
+        // Frame manager storage, mapped later in its window
+        let mut frame = storage;
+        while frame < storage + storage_size as PhysicalAddress {
+            bitmap.alloc_frame_by_address(frame).unwrap();
+            frame += PAGE_SIZE_4K as PhysicalAddress;
+        }
+
+        // Boot modules, mapped later by the initrd
+        for module in multiboot_info.get_modules() {
+            let mut frame = module.mod_start as PhysicalAddress & !0xFFF;
+            while frame < module.mod_end as PhysicalAddress {
+                bitmap.alloc_frame_by_address(frame).unwrap();
+                frame += PAGE_SIZE_4K as PhysicalAddress;
+            }
+        }
 
-    if enable_paging {
-        PAGE_DIRECTORY.lock().clear();
+        // Gdt, ps2 ports, VGA
+        bitmap.alloc_frame_by_address(0x0).ok();
+        bitmap.alloc_frame_by_address(0xb8000).ok();
 
-        // Gdt, ps2 ports
-        BITMAP.lock().alloc_frame_by_address(0x0).unwrap();
-        PAGE_DIRECTORY.lock().map_pages(0x0, 0x0, 0x3).unwrap();
+        // Kernel frames
+        let kernel_first_frame = kernel_to_physical(get_kernel_start() as usize) & !0xFFF;
+        let kernel_last_frame = kernel_to_physical(get_kernel_end() as usize) & !0xFFF;
+        let mut i = kernel_first_frame;
+        while i <= kernel_last_frame {
+            bitmap.alloc_frame_by_address(i as PhysicalAddress).unwrap();
+            i += PAGE_SIZE_4K;
+        }
+    });
 
-        // VGA
-        BITMAP.lock().alloc_frame_by_address(0xb8000).unwrap();
-        PAGE_DIRECTORY
-            .lock()
-            .map_pages(0xb8000, 0xb8000, 0x3)
-            .unwrap();
+    let multiboot_start = kernel_to_physical(multiboot_info.inner as usize);
+    let multiboot_first_frame = multiboot_start & !0xFFF;
+    let multiboot_end = multiboot_start + multiboot_info.total_size();
+    if mode == PagingMode::Disabled {
+        frame_manager(|bitmap| {
+            let mut frame = multiboot_first_frame;
+            while frame < multiboot_end {
+                bitmap.alloc_frame_by_address(frame as PhysicalAddress).ok();
+                frame += PAGE_SIZE_4K;
+            }
+        });
+        return;
+    }
+
+    // The new directory is built through the bootstrap mapping
+    assert!(
+        PDPT_ADDRESS < BOOT_MAPPING_END,
+        "page directory out of the boot mapping"
+    );
+    frame_manager(|bitmap| {
+        for i in 0..recursive_entries().len() {
+            bitmap
+                .alloc_frame_by_address((PAGE_DIR_ADDRESS + i * PAGE_SIZE_4K) as PhysicalAddress)
+                .unwrap();
+        }
+        if is_pae() {
+            bitmap
+                .alloc_frame_by_address(PDPT_ADDRESS as PhysicalAddress)
+                .unwrap();
+        }
+    });
+    current_directory(|directory| directory.clear());
 
-        // Kernel mapping
-        let mut i = kernel_first_page;
-        while i <= kernel_last_page {
-            BITMAP.lock().alloc_frame_by_address(i).unwrap();
-            PAGE_DIRECTORY.lock().map_pages(i, i, 0x3).unwrap();
-            i += 0x1000;
+    // Low memory and kernel mapping
+    let large_pages_end = get_first_large_page_after_kernel() as usize;
+    let mut page = physical_to_kernel(0x0);
+    while page < large_pages_end {
+        let pages = page..page + large_page_size();
+        if is_pae() && kernel_sections::overlaps(&pages) {
+            current_directory(|directory| kernel_sections::map(directory, pages)).unwrap();
+        } else {
+            // Without PAE, the kernel image is mapped as a whole
+            let flags = match kernel_sections::overlaps(&pages) {
+                true => PageFlags::KERNEL_DATA,
+                false => KERNEL_MEMORY_FLAGS,
+            };
+            current_directory(|directory| {
+                directory.map_large_page(kernel_to_physical(page) as PhysicalAddress, page, flags)
+            });
         }
+        page += large_page_size();
+    }
 
-        // MultibootInfo
-        if BITMAP
-            .lock()
-            .alloc_frame_by_address(multiboot_frame_add)
-            .is_ok()
+    // MultibootInfo, kept mapped for its tags
+    let mut frame = multiboot_first_frame;
+    while frame < multiboot_end {
+        let already_mapped = physical_to_kernel(frame) < large_pages_end;
+        if frame_manager(|bitmap| bitmap.alloc_frame_by_address(frame as PhysicalAddress)).is_ok()
+            && !already_mapped
         {
-            PAGE_DIRECTORY
-                .lock()
-                .map_pages(multiboot_frame_add, multiboot_frame_add, 0x3)
+            current_directory(|directory| {
+                directory.map_pages(
+                    frame as PhysicalAddress,
+                    physical_to_kernel(frame),
+                    PageFlags::KERNEL_DATA,
+                )
+            })
+            .unwrap();
+        }
+        frame += PAGE_SIZE_4K;
+    }
+
+    // Frame manager storage, out of the bootstrap mapping once paging is
+    // enabled
+    current_directory(|directory| {
+        for i in (0..storage_size).step_by(PAGE_SIZE_4K) {
+            directory
+                .map_pages(
+                    storage + i as PhysicalAddress,
+                    FRAME_MANAGER_WINDOW + i,
+                    KERNEL_MEMORY_FLAGS,
+                )
                 .unwrap();
         }
 
+        // Kernel space page tables, shared by all the address spaces
+        directory
+            .preallocate_tables(directory_index(KERNEL_BASE)..recursive_entries().start)
+            .unwrap();
+
         // Recursive page directory trick
-        PAGE_DIRECTORY.lock().set_entry(1023, PAGE_DIR_ADDRESS, 0x3);
-        enable(PAGE_DIR_ADDRESS);
-        *PAGE_DIRECTORY.lock() = unsafe {
-            PageDirectory(
-                Unique::new_unchecked((0x3FFusize << 22 | 0x3FFusize << 12) as *mut _),
-                true,
-            )
-        };
+        for (i, entry) in recursive_entries().enumerate() {
+            directory.set_entry(
+                entry,
+                (PAGE_DIR_ADDRESS + i * PAGE_SIZE_4K) as PhysicalAddress,
+                PageFlags::KERNEL_DATA,
+            );
+        }
+    });
+
+    if is_pae() {
+        let mut directories: [PhysicalAddress; 4] = [0; 4];
+        for (i, directory) in directories.iter_mut().enumerate() {
+            *directory = (PAGE_DIR_ADDRESS + i * PAGE_SIZE_4K) as PhysicalAddress;
+        }
+        unsafe { PageDirectoryPointerTable::new(physical_to_kernel(PDPT_ADDRESS)) }
+            .set_directories(&directories);
+        // The mode switch runs from the physical address of the kernel
+        let identity_end = kernel_to_physical(large_pages_end);
+        current_directory(|directory| {
+            let mut page = 0x0;
+            while page < identity_end {
+                directory.map_large_page(page as PhysicalAddress, page, PageFlags::KERNEL_DATA);
+                page += large_page_size();
+            }
+        });
+        if has_no_execute() {
+            enable_no_execute();
+        }
+        enable_pae(PDPT_ADDRESS);
+        unsafe { frame_manager(|bitmap| bitmap.relocate(FRAME_MANAGER_WINDOW)) };
+        current_directory(|directory| {
+            *directory = unsafe { PageDirectory::new(paging_mode::directory_address(), true) };
+            let mut page = 0x0;
+            while page < identity_end {
+                directory.unlink_page(page);
+                page += large_page_size();
+            }
+        });
     } else {
-        BITMAP.lock().alloc_frame_by_address(0x0).unwrap();
-        BITMAP.lock().alloc_frame_by_address(0xb8000).unwrap();
-        let mut i = kernel_first_page;
-        while i <= kernel_last_page {
-            BITMAP.lock().alloc_frame_by_address(i).unwrap();
-            i += 0x1000;
-        }
-        BITMAP
-            .lock()
-            .alloc_frame_by_address(multiboot_frame_add)
-            .ok();
+        enable(PAGE_DIR_ADDRESS);
+        unsafe { frame_manager(|bitmap| bitmap.relocate(FRAME_MANAGER_WINDOW)) };
+        current_directory(|directory| {
+            *directory = unsafe { PageDirectory::new(paging_mode::directory_address(), true) }
+        });
+    }
+}
+
+/// First free RAM of `size` bytes, reachable through the bootstrap mapping
+///
+/// The low memory, the kernel image, the boot information and the boot
+/// modules are avoided.
+fn find_storage(multiboot_info: MultibootInfo, size: PhysicalAddress) -> Option<PhysicalAddress> {
+    let low_memory_end = 0x100000;
+    let kernel = kernel_to_physical(get_kernel_start() as usize) as PhysicalAddress
+        ..kernel_to_physical(get_kernel_end() as usize) as PhysicalAddress;
+    let multiboot_start = kernel_to_physical(multiboot_info.inner as usize) as PhysicalAddress;
+    let multiboot =
+        multiboot_start..multiboot_start + multiboot_info.total_size() as PhysicalAddress;
+    // End of the used memory overlapping `range`
+    let used = |range: Range<PhysicalAddress>| {
+        multiboot_info
+            .get_modules()
+            .map(|module| module.mod_start as PhysicalAddress..module.mod_end as PhysicalAddress)
+            .chain(once(kernel.clone()))
+            .chain(once(multiboot.clone()))
+            .find(|used| used.start < range.end && range.start < used.end)
+            .map(|used| used.end)
+    };
+    let align = |address: PhysicalAddress| (address + 0xFFF) & !0xFFF;
+
+    let mem_map = multiboot_info.get_memory_map()?;
+    for mem_entry in mem_map.entries().filter(|entry| entry.typ == 1) {
+        let end = (mem_entry.base_addr + mem_entry.length).min(BOOT_MAPPING_END as PhysicalAddress);
+        let mut start = align(mem_entry.base_addr.max(low_memory_end));
+        while start + size <= end {
+            match used(start..start + size) {
+                Some(used_end) => start = align(used_end),
+                None => return Some(start),
+            }
+        }
     }
+    None
 }
 
 use spin::Mutex;
 
 /// Unique access to the page directory
-pub static PAGE_DIRECTORY: Mutex<PageDirectory> = Mutex::new(PageDirectory(
-    unsafe { Unique::new_unchecked(PAGE_DIR_ADDRESS as *mut _) },
-    false,
-));
+///
+/// Until paging is set up, the directory frame is reached through the
+/// bootstrap mapping.
+static PAGE_DIRECTORY: Mutex<PageDirectory> =
+    Mutex::new(unsafe { PageDirectory::new(physical_to_kernel(PAGE_DIR_ADDRESS), false) });
+
+/// Access the page directory of the current address space, with interrupts
+/// disabled
+///
+/// Page faults map pages, they must not find it locked.
+pub fn current_directory<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut PageDirectory) -> R,
+{
+    idt::without_interrupts(|| f(&mut PAGE_DIRECTORY.lock()))
+}
+
+/// Physical address of the current page directory
+pub fn current_address_space() -> usize {
+    let cr3: usize;
+    unsafe {
+        asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack));
+    }
+    cr3 & !0xFFF
+}
+
+/// Physical address loaded in CR3 for the kernel address space
+///
+/// Its page directory, or its page directory pointer table with PAE.
+pub fn kernel_address_space() -> usize {
+    match is_pae() {
+        true => PDPT_ADDRESS,
+        false => PAGE_DIR_ADDRESS,
+    }
+}
+
+/// Load another page directory
+///
+/// The kernel space being shared, the kernel keeps running. The user space
+/// and the `PAGE_DIRECTORY` content are those of the new directory.
+pub fn switch_address_space(page_directory: usize) {
+    unsafe {
+        asm!("mov cr3, {}", in(reg) page_directory, options(nostack));
+    }
+}
+
+/// Create a new address space
+///
+/// Its kernel space is shared with the current one, its user space is empty.
+/// Return the physical address loaded in CR3: its page directory, or its page
+/// directory pointer table with PAE.
+pub fn create_address_space() -> Result<usize, VirtualMemoryError> {
+    // The page directories, then the pointer table with PAE
+    let n_directories = recursive_entries().len();
+    let n_frames = n_directories + is_pae() as usize;
+    let mut frames: [PhysicalAddress; 5] = [0; 5];
+    for i in 0..n_frames {
+        let frame = match i < n_directories {
+            true => frame_manager(|bitmap| bitmap.alloc_frame()),
+            // Loaded in CR3, only 32 bit wide
+            false => frame_manager(|bitmap| bitmap.alloc_frame_below(LEGACY_MEMORY_END)),
+        };
+        match frame {
+            Ok(frame) => frames[i] = frame,
+            Err(e) => {
+                release_frames(&frames[..i]);
+                return Err(VirtualMemoryError::PhysicalMemoryError(e));
+            }
+        }
+    }
+    let (directories, pdpt) = frames[..n_frames].split_at(n_directories);
+
+    // The kernel space tables of the temporary pages always exist
+    current_directory(|directory| {
+        for (i, &frame) in directories.iter().enumerate() {
+            directory
+                .map_pages(
+                    frame,
+                    TEMPORARY_DIRECTORY + i * PAGE_SIZE_4K,
+                    PageFlags::KERNEL_DATA,
+                )
+                .unwrap();
+        }
+        let mut new_directory = unsafe { PageDirectory::new(TEMPORARY_DIRECTORY, false) };
+
+        new_directory.clear();
+        for idx in directory_index(KERNEL_BASE)..recursive_entries().start {
+            let entry = directory.entry(idx);
+            new_directory.set_entry(idx, entry.page_table_address(), entry.flags());
+        }
+        for (i, idx) in recursive_entries().enumerate() {
+            new_directory.set_entry(idx, directories[i], PageFlags::KERNEL_DATA);
+        }
+        for i in 0..n_directories {
+            directory.unlink_page(TEMPORARY_DIRECTORY + i * PAGE_SIZE_4K);
+        }
+
+        match pdpt.first() {
+            Some(&pdpt) => {
+                directory
+                    .map_pages(pdpt, TEMPORARY_PAGE, PageFlags::KERNEL_DATA)
+                    .unwrap();
+                unsafe { PageDirectoryPointerTable::new(TEMPORARY_PAGE) }
+                    .set_directories(directories);
+                directory.unlink_page(TEMPORARY_PAGE);
+                Ok(pdpt as usize)
+            }
+            None => Ok(directories[0] as usize),
+        }
+    })
+}
+
+/// Free frames that are not mapped anywhere
+fn release_frames(frames: &[PhysicalAddress]) {
+    frame_manager(|bitmap| {
+        for &frame in frames {
+            bitmap.free_frame(frame).ok();
+        }
+    });
+}
+
+/// Create a copy of the current address space
+///
+/// Every mapped page of the user space is shared with the copy, writable pages
+/// becoming copy on write. A frame shared too many times is copied instead.
+/// The demand paged regions are reserved in the copy too.
+/// Return the physical address of the new page directory.
+pub fn clone_address_space() -> Result<usize, VirtualMemoryError> {
+    let parent = current_address_space();
+    let child = create_address_space()?;
+    // Pages not accessed yet stay to be backed, in both spaces
+    if let Err(e) = demand_paging::clone_regions(parent, child) {
+        destroy_address_space(child).ok();
+        return Err(e);
+    }
+    // Pages go through a kernel space buffer, reachable from both spaces
+    let mut buffer = vec![0u8; PAGE_SIZE_4K].into_boxed_slice();
+
+    let copy_page = |page: usize, buffer: &mut [u8]| -> Result<(), VirtualMemoryError> {
+        let flags = match current_directory(|directory| directory.get_page_flags(page)) {
+            // The copy is private
+            Some(flags) if flags.contains(PageFlags::COPY_ON_WRITE) => {
+                flags & copy_on_write::ACCESS_FLAGS | PageFlags::WRITABLE
+            }
+            Some(flags) => flags & copy_on_write::ACCESS_FLAGS,
+            None => return Ok(()),
+        };
+        let frame = frame_manager(|bitmap| bitmap.alloc_frame())
+            .map_err(VirtualMemoryError::PhysicalMemoryError)?;
+        unsafe {
+            core::ptr::copy_nonoverlapping(page as *const u8, buffer.as_mut_ptr(), PAGE_SIZE_4K);
+        }
+        // No context switch may happen while the child space is loaded
+        idt::without_interrupts(|| {
+            switch_address_space(child);
+            let mapped = current_directory(|directory| directory.map_pages(frame, page, flags));
+            if mapped.is_ok() {
+                unsafe {
+                    core::ptr::copy_nonoverlapping(buffer.as_ptr(), page as *mut u8, PAGE_SIZE_4K);
+                }
+            }
+            switch_address_space(parent);
+            mapped.map_err(|e| {
+                frame_manager(|bitmap| bitmap.free_frame(frame)).ok();
+                e
+            })
+        })
+    };
+
+    for d_offset in directory_index(USER_SPACE_START)..directory_index(USER_SPACE_END) {
+        if !current_directory(|directory| directory.entry(d_offset).is_present()) {
+            continue;
+        }
+        for t_offset in 0..table_len() {
+            let page = page_address(d_offset, t_offset);
+            let cloned = match copy_on_write::share_page(page, child) {
+                Ok(true) => Ok(()),
+                Ok(false) => copy_page(page, &mut buffer),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = cloned {
+                destroy_address_space(child).ok();
+                return Err(e);
+            }
+        }
+    }
+    Ok(child)
+}
+
+/// Free an address space created by `create_address_space`
+///
+/// Every page of its user space is freed, as well as its page directory. It
+/// must not be the current address space.
+pub fn destroy_address_space(page_directory: usize) -> Result<(), VirtualMemoryError> {
+    assert_ne!(
+        page_directory,
+        current_address_space(),
+        "cannot destroy the current address space"
+    );
+
+    // The page directories, then the pointer table with PAE
+    let mut frames: [PhysicalAddress; 5] = [0; 5];
+    // Its page tables can only be reached through its own recursive mapping,
+    // no context switch may happen while it is loaded
+    idt::without_interrupts(|| {
+        let previous = current_address_space();
+        switch_address_space(page_directory);
+        let cleared = current_directory(|directory| {
+            for (i, idx) in recursive_entries().enumerate() {
+                frames[i] = directory.entry(idx).page_table_address();
+            }
+            directory
+                .clear_tables(directory_index(USER_SPACE_START)..directory_index(USER_SPACE_END))
+        });
+        switch_address_space(previous);
+        cleared
+    })?;
+    demand_paging::drop_regions(page_directory);
+
+    let mut n_frames = recursive_entries().len();
+    if is_pae() {
+        frames[n_frames] = page_directory as PhysicalAddress;
+        n_frames += 1;
+    }
+    frame_manager(|bitmap| {
+        for &frame in frames[..n_frames].iter() {
+            bitmap
+                .free_frame(frame)
+                .map_err(VirtualMemoryError::PhysicalMemoryError)?;
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,154 @@
+//! Flags of the page directory and page table entries
+
+use core::fmt;
+use core::ops::{BitAnd, BitOr, BitOrAssign, Not};
+
+/// The low 12 bits of an entry, and the no execute bit of PAE entries
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub struct PageFlags(u64);
+
+impl PageFlags {
+    pub const PRESENT: PageFlags = PageFlags(0x1);
+    pub const WRITABLE: PageFlags = PageFlags(0x2);
+    /// Accessible from ring 3
+    pub const USER: PageFlags = PageFlags(0x4);
+    pub const WRITE_THROUGH: PageFlags = PageFlags(0x8);
+    pub const CACHE_DISABLE: PageFlags = PageFlags(0x10);
+    /// Set by the CPU when the page is accessed
+    pub const ACCESSED: PageFlags = PageFlags(0x20);
+    /// Set by the CPU when the page is written, page table entries only
+    pub const DIRTY: PageFlags = PageFlags(0x40);
+    /// The directory entry maps a 4 MiB page instead of a page table, 2 MiB
+    /// with PAE
+    pub const LARGE_PAGE: PageFlags = PageFlags(0x80);
+    /// Kept in the TLB across address space switches, if enabled in CR4
+    pub const GLOBAL: PageFlags = PageFlags(0x100);
+    /// Bits left to the system
+    pub const AVAILABLE_0: PageFlags = PageFlags(0x200);
+    pub const AVAILABLE_1: PageFlags = PageFlags(0x400);
+    pub const AVAILABLE_2: PageFlags = PageFlags(0x800);
+    /// Instructions can't be fetched from the page, PAE only
+    pub const NO_EXECUTE: PageFlags = PageFlags(1 << 63);
+
+    /// The frame is shared, read only, and copied on the first write
+    pub const COPY_ON_WRITE: PageFlags = PageFlags::AVAILABLE_0;
+
+    /// Kernel read write pages
+    pub const KERNEL_DATA: PageFlags = PageFlags::PRESENT.union(PageFlags::WRITABLE);
+    /// User read write pages
+    pub const USER_DATA: PageFlags = PageFlags::KERNEL_DATA.union(PageFlags::USER);
+
+    const ALL: u64 = 0xFFF | PageFlags::NO_EXECUTE.0;
+
+    const NAMES: [(PageFlags, &'static str); 13] = [
+        (PageFlags::PRESENT, "present"),
+        (PageFlags::WRITABLE, "writable"),
+        (PageFlags::USER, "user"),
+        (PageFlags::WRITE_THROUGH, "write-through"),
+        (PageFlags::CACHE_DISABLE, "cache-disable"),
+        (PageFlags::ACCESSED, "accessed"),
+        (PageFlags::DIRTY, "dirty"),
+        (PageFlags::LARGE_PAGE, "large"),
+        (PageFlags::GLOBAL, "global"),
+        (PageFlags::COPY_ON_WRITE, "copy-on-write"),
+        (PageFlags::AVAILABLE_1, "available-1"),
+        (PageFlags::AVAILABLE_2, "available-2"),
+        (PageFlags::NO_EXECUTE, "no-execute"),
+    ];
+
+    pub const fn empty() -> PageFlags {
+        PageFlags(0)
+    }
+
+    /// Flags of an entry, the other bits are dropped
+    pub const fn from_bits_truncate(bits: u64) -> PageFlags {
+        PageFlags(bits & PageFlags::ALL)
+    }
+
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub const fn union(self, other: PageFlags) -> PageFlags {
+        PageFlags(self.0 | other.0)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Every flag of `other` is set
+    pub fn contains(self, other: PageFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Some flag of `other` is set
+    pub fn intersects(self, other: PageFlags) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn insert(&mut self, other: PageFlags) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: PageFlags) {
+        self.0 &= !other.0;
+    }
+
+    pub fn set(&mut self, other: PageFlags, value: bool) {
+        if value {
+            self.insert(other);
+        } else {
+            self.remove(other);
+        }
+    }
+}
+
+impl BitOr for PageFlags {
+    type Output = PageFlags;
+
+    fn bitor(self, other: PageFlags) -> PageFlags {
+        self.union(other)
+    }
+}
+
+impl BitOrAssign for PageFlags {
+    fn bitor_assign(&mut self, other: PageFlags) {
+        self.insert(other);
+    }
+}
+
+impl BitAnd for PageFlags {
+    type Output = PageFlags;
+
+    fn bitand(self, other: PageFlags) -> PageFlags {
+        PageFlags(self.0 & other.0)
+    }
+}
+
+impl Not for PageFlags {
+    type Output = PageFlags;
+
+    fn not(self) -> PageFlags {
+        PageFlags::from_bits_truncate(!self.0)
+    }
+}
+
+impl fmt::Display for PageFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "none");
+        }
+        let mut names = PageFlags::NAMES
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| name);
+        if let Some(name) = names.next() {
+            write!(f, "{}", name)?;
+        }
+        for name in names {
+            write!(f, " | {}", name)?;
+        }
+        Ok(())
+    }
+}
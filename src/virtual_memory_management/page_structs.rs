@@ -1,31 +1,90 @@
 use core::fmt;
-use core::ptr::Unique;
+use core::ops::Range;
 
-use crate::physical_memory_management::PhysicalMemoryError;
+use super::paging_mode::{
+    self, directory_index, directory_len, is_pae, large_page_size, page_address, recursive_entries,
+    table_index, table_len,
+};
+use super::{physical_to_kernel, PageFlags, BOOT_MAPPING_END};
+use crate::physical_memory_management::{PhysicalAddress, PhysicalMemoryError};
 
 #[derive(Debug)]
 pub enum VirtualMemoryError {
     PhysicalMemoryError(PhysicalMemoryError),
+    /// No demand paged region can be reserved anymore
+    TooManyRegions,
+    /// No page is mapped at this address
+    PageNotMapped(usize),
 }
 
-pub struct PageTableEntry(usize);
+/// Frame address bits of an entry
+const ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// Read the entry `index` of the paging structure at `address`
+///
+/// Entries are 64 bit wide with PAE, 32 bit wide otherwise.
+fn read_entry(address: usize, index: usize) -> u64 {
+    unsafe {
+        match is_pae() {
+            true => core::ptr::read_volatile((address as *const u64).add(index)),
+            false => core::ptr::read_volatile((address as *const u32).add(index)) as u64,
+        }
+    }
+}
+
+/// Write the entry `index` of the paging structure at `address`
+///
+/// The no execute flag is dropped where the CPU would reserve it.
+fn write_entry(address: usize, index: usize, entry: u64) {
+    let entry = match paging_mode::has_no_execute() {
+        true => entry,
+        false => entry & !PageFlags::NO_EXECUTE.bits(),
+    };
+    unsafe {
+        if is_pae() {
+            // The low half holds the present flag, the entry is never seen
+            // present with the high half of another one
+            let halves = (address as *mut u32).add(2 * index);
+            if core::ptr::read_volatile(halves.add(1)) != (entry >> 32) as u32 {
+                core::ptr::write_volatile(halves, 0);
+                core::ptr::write_volatile(halves.add(1), (entry >> 32) as u32);
+            }
+            core::ptr::write_volatile(halves, entry as u32);
+        } else {
+            assert_eq!(
+                0,
+                entry >> 32,
+                "frame out of reach without PAE: {:#x}",
+                entry & ADDRESS_MASK
+            );
+            core::ptr::write_volatile((address as *mut u32).add(index), entry as u32);
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct PageTableEntry(u64);
 
 impl PageTableEntry {
-    fn new(address: usize, flags: usize) -> PageTableEntry {
+    fn new(address: PhysicalAddress, flags: PageFlags) -> PageTableEntry {
         assert_eq!(0, address & 0xFFF);
-        PageTableEntry(address | flags)
+        PageTableEntry(address | flags.bits())
     }
 
-    pub fn page_frame_address(&self) -> usize {
-        (self.0 & 0xFFFFF000) as usize
+    pub fn page_frame_address(&self) -> PhysicalAddress {
+        self.0 & ADDRESS_MASK
+    }
+
+    pub fn flags(&self) -> PageFlags {
+        PageFlags::from_bits_truncate(self.0)
     }
 
     pub fn is_present(&self) -> bool {
-        self.0 & 0x1 != 0
+        self.flags().contains(PageFlags::PRESENT)
     }
 
-    fn is_wr(&self) -> bool {
-        self.0 & (0x1 << 1) != 0
+    pub fn is_copy_on_write(&self) -> bool {
+        self.flags().contains(PageFlags::COPY_ON_WRITE)
     }
 }
 
@@ -33,124 +92,303 @@ impl fmt::Display for PageTableEntry {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "Frame physical address: {:#010x}, Present: {}, Write/Read: {}",
+            "Frame physical address: {:#010x}, Flags: {}",
             self.page_frame_address(),
-            self.is_present(),
-            self.is_wr()
+            self.flags()
         )
     }
 }
 
-pub struct PageTable(pub Unique<[PageTableEntry; 1024]>);
+/// A page table, reached at its virtual address
+pub struct PageTable(usize);
 
 impl PageTable {
-    pub fn ref_table(&self) -> &[PageTableEntry; 1024] {
-        unsafe { self.0.as_ref() }
-    }
-
-    fn mut_table(&mut self) -> &mut [PageTableEntry; 1024] {
-        unsafe { self.0.as_mut() }
+    pub fn entry(&self, index: usize) -> PageTableEntry {
+        assert!(
+            index < table_len(),
+            "page table index out of range: {}",
+            index
+        );
+        PageTableEntry(read_entry(self.0, index))
     }
 
     fn clear(&mut self) {
-        for i in 0..self.ref_table().len() {
-            self.mut_table()[i] = PageTableEntry::new(0, 0)
+        for i in 0..table_len() {
+            self.set_entry(i, 0, PageFlags::empty());
         }
     }
 
-    fn set_entry(&mut self, index: usize, address: usize, flags: usize) {
-        self.mut_table()[index] = PageTableEntry::new(address, flags);
+    fn set_entry(&mut self, index: usize, address: PhysicalAddress, flags: PageFlags) {
+        assert!(
+            index < table_len(),
+            "page table index out of range: {}",
+            index
+        );
+        write_entry(self.0, index, PageTableEntry::new(address, flags).0);
     }
 }
 
 impl fmt::Display for PageTable {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for (idx, entry) in self
-            .ref_table()
-            .iter()
-            .enumerate()
-            .filter(|(_, e)| e.is_present())
-        {
-            if let Err(e) = writeln!(f, "{:04}: {}", idx, entry) {
-                return Err(e);
+        for idx in 0..table_len() {
+            let entry = self.entry(idx);
+            if entry.is_present() {
+                writeln!(f, "{:04}: {}", idx, entry)?;
             }
         }
         Ok(())
     }
 }
 
-pub struct PageDirectoryEntry(usize);
+#[derive(Copy, Clone)]
+pub struct PageDirectoryEntry(u64);
 
 impl PageDirectoryEntry {
-    fn new(address: usize, flags: usize) -> PageDirectoryEntry {
+    fn new(address: PhysicalAddress, flags: PageFlags) -> PageDirectoryEntry {
         assert_eq!(0, address & 0xFFF);
-        PageDirectoryEntry(address | flags)
+        PageDirectoryEntry(address | flags.bits())
     }
 
-    pub fn page_table_address(&self) -> usize {
-        (self.0 & 0xFFFFF000) as usize
+    /// Address of the page table, or of the first frame of a large page
+    pub fn page_table_address(&self) -> PhysicalAddress {
+        self.0 & ADDRESS_MASK
+    }
+
+    pub fn flags(&self) -> PageFlags {
+        PageFlags::from_bits_truncate(self.0)
     }
 
     pub fn is_present(&self) -> bool {
-        self.0 & 0x1 != 0
+        self.flags().contains(PageFlags::PRESENT)
+    }
+
+    fn is_user(&self) -> bool {
+        self.flags().contains(PageFlags::USER)
     }
 
-    fn is_wr(&self) -> bool {
-        self.0 & (0x1 << 1) != 0
+    /// The entry maps a large page, and has no page table
+    pub fn is_large(&self) -> bool {
+        self.flags().contains(PageFlags::LARGE_PAGE)
     }
 }
 
 impl fmt::Display for PageDirectoryEntry {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = match self.is_large() {
+            true => "Frame",
+            false => "Table",
+        };
         write!(
             f,
-            "Table physical address: {:#010x}, Present: {}, Write/Read: {}",
+            "{} physical address: {:#010x}, Flags: {}",
+            kind,
             self.page_table_address(),
-            self.is_present(),
-            self.is_wr()
+            self.flags()
         )
     }
 }
 
-use crate::physical_memory_management::BITMAP;
+/// Page directory pointer table of PAE paging, pointing to the four page
+/// directories
+///
+/// Its address is loaded in CR3, it must lie below 4 GiB.
+pub struct PageDirectoryPointerTable(usize);
+
+impl PageDirectoryPointerTable {
+    /// The table reached at the virtual address `address`
+    pub unsafe fn new(address: usize) -> PageDirectoryPointerTable {
+        PageDirectoryPointerTable(address)
+    }
+
+    /// Point to the page directories, in address order
+    ///
+    /// The CPU only reads the table when CR3 is loaded.
+    pub fn set_directories(&mut self, directories: &[PhysicalAddress]) {
+        assert_eq!(4, directories.len(), "PAE uses four page directories");
+        for (i, &directory) in directories.iter().enumerate() {
+            unsafe {
+                core::ptr::write_volatile(
+                    (self.0 as *mut u64).add(i),
+                    directory | PageFlags::PRESENT.bits(),
+                );
+            }
+        }
+    }
+}
+
+use crate::physical_memory_management::{frame_manager, Zone, PAGE_SIZE_4K};
 
-pub struct PageDirectory(pub Unique<[PageDirectoryEntry; 1024]>, pub bool);
+/// Flags set by the CPU
+const USAGE_FLAGS: PageFlags = PageFlags::ACCESSED.union(PageFlags::DIRTY);
+
+/// Pages mapped to contiguous frames, with the same flags
+#[derive(Debug, Copy, Clone)]
+pub struct MappedRegion {
+    pub start: usize,
+    pub frame: PhysicalAddress,
+    pub size: usize,
+    /// Without the accessed and dirty flags, which differ between pages
+    pub flags: PageFlags,
+}
+
+impl fmt::Display for MappedRegion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:#010x}-{:#010x} -> {:#010x}, {} pages, {}",
+            self.start,
+            self.start + (self.size - 1),
+            self.frame,
+            self.size / PAGE_SIZE_4K,
+            self.flags
+        )
+    }
+}
+
+/// Iterator over the mapped regions of a directory
+pub struct MappedRegions<'a> {
+    directory: &'a PageDirectory,
+    /// Next page number to look at
+    page: usize,
+    /// Page number ending the iteration
+    last: usize,
+}
+
+impl<'a> Iterator for MappedRegions<'a> {
+    type Item = MappedRegion;
+
+    fn next(&mut self) -> Option<MappedRegion> {
+        let mut region: Option<MappedRegion> = None;
+        while self.page < self.last {
+            let address = self.page << 12;
+            if !self.directory.entry(directory_index(address)).is_present() {
+                if region.is_some() {
+                    break;
+                }
+                // Skip the whole missing table
+                self.page = (self.page | (table_len() - 1)) + 1;
+                continue;
+            }
+            match (self.directory.translate(address), region.as_mut()) {
+                (Some((frame, flags)), Some(r))
+                    if r.frame + r.size as PhysicalAddress == frame
+                        && r.flags == flags & !USAGE_FLAGS =>
+                {
+                    r.size += PAGE_SIZE_4K
+                }
+                (_, Some(_)) => break,
+                (Some((frame, flags)), None) => {
+                    region = Some(MappedRegion {
+                        start: address,
+                        frame,
+                        size: PAGE_SIZE_4K,
+                        flags: flags & !USAGE_FLAGS,
+                    })
+                }
+                (None, None) => (),
+            }
+            self.page += 1;
+        }
+        region
+    }
+}
+
+/// A page directory, reached at its virtual address
+///
+/// With PAE, the four page directories are handled as one, they must be
+/// contiguous.
+pub struct PageDirectory {
+    address: usize,
+    /// Loaded and reached through its recursive entries
+    enabled: bool,
+}
 
 impl PageDirectory {
-    pub fn is_enabled(&self) -> bool {
-        self.1
+    /// The directory at the virtual address `address`
+    ///
+    /// Once `enabled`, its page tables are reached through the recursive
+    /// entries, or else through the bootstrap mapping.
+    pub const unsafe fn new(address: usize, enabled: bool) -> PageDirectory {
+        PageDirectory { address, enabled }
     }
 
-    pub fn ref_dir(&self) -> &[PageDirectoryEntry; 1024] {
-        unsafe { self.0.as_ref() }
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
     }
 
-    fn mut_dir(&mut self) -> &mut [PageDirectoryEntry; 1024] {
-        unsafe { self.0.as_mut() }
+    pub fn entry(&self, index: usize) -> PageDirectoryEntry {
+        assert!(
+            index < directory_len(),
+            "page directory index out of range: {}",
+            index
+        );
+        PageDirectoryEntry(read_entry(self.address, index))
     }
 
     pub fn clear(&mut self) {
-        for i in 0..self.ref_dir().len() {
-            self.mut_dir()[i] = PageDirectoryEntry::new(0, 0)
+        for i in 0..directory_len() {
+            self.set_entry(i, 0, PageFlags::empty());
         }
     }
 
+    /// Address of a page table, through the recursive entry once enabled, or
+    /// else through the bootstrap mapping
     fn get_table_linear_add(&self, offset: usize) -> usize {
         match self.is_enabled() {
-            true => 0xFFC00000usize + (offset << 12),
-            false => self.ref_dir()[offset].page_table_address(),
+            true => paging_mode::tables_address() + (offset << 12),
+            false => {
+                let table = self.entry(offset).page_table_address();
+                assert!(
+                    table < BOOT_MAPPING_END as PhysicalAddress,
+                    "page table out of the boot mapping: {:#010x}",
+                    table
+                );
+                physical_to_kernel(table as usize)
+            }
         }
     }
 
-    pub fn set_entry(&mut self, index: usize, address: usize, flags: usize) {
-        self.mut_dir()[index] = PageDirectoryEntry::new(address, flags);
+    fn table(&self, offset: usize) -> PageTable {
+        PageTable(self.get_table_linear_add(offset))
+    }
+
+    pub fn set_entry(&mut self, index: usize, address: PhysicalAddress, flags: PageFlags) {
+        assert!(
+            index < directory_len(),
+            "page directory index out of range: {}",
+            index
+        );
+        write_entry(
+            self.address,
+            index,
+            PageDirectoryEntry::new(address, flags).0,
+        );
+    }
+
+    /// Frame of a new page table
+    ///
+    /// Until the directory is enabled, tables are reached through the
+    /// bootstrap mapping, they are taken below 16 MiB.
+    fn alloc_table(&self) -> Result<PhysicalAddress, VirtualMemoryError> {
+        let frame = match self.is_enabled() {
+            true => frame_manager(|bitmap| bitmap.alloc_frame()),
+            false => frame_manager(|bitmap| bitmap.alloc_frame_in(Zone::Dma)),
+        };
+        frame.map_err(VirtualMemoryError::PhysicalMemoryError)
+    }
+
+    /// Check that `d_offset` is out of the self referencing directory trick
+    fn assert_mappable(d_offset: usize, virtual_page_address: usize) {
+        assert!(d_offset < recursive_entries().start,
+            "trying to map page at: {}. All addresses over {:#010x} are reserved by the self referencing directory trick.",
+            virtual_page_address, paging_mode::tables_address());
     }
 
     pub fn map_pages(
         &mut self,
-        physical_page_address: usize,
+        physical_page_address: PhysicalAddress,
         virtual_page_address: usize,
-        flags: usize,
+        flags: PageFlags,
     ) -> Result<(), VirtualMemoryError> {
         assert_eq!(
             0,
@@ -165,36 +403,38 @@ impl PageDirectory {
             virtual_page_address
         );
 
-        let d_offset = virtual_page_address >> 22;
-        let t_offset = (virtual_page_address & 0x3FF000) >> 12;
-
-        assert!(d_offset != 1023,
-            "trying to map page at: {}. All addresses over 0xFFC00000 are reserved by the self referencing directory trick.",
-            virtual_page_address);
+        let d_offset = directory_index(virtual_page_address);
+        let t_offset = table_index(virtual_page_address);
+        PageDirectory::assert_mappable(d_offset, virtual_page_address);
 
         let mut page_table: PageTable;
-        let page_table_add: usize;
-        if !self.ref_dir()[d_offset].is_present() {
-            page_table_add = BITMAP
-                .lock()
-                .alloc_frame()
-                .map_err(VirtualMemoryError::PhysicalMemoryError)?;
-            self.set_entry(d_offset, page_table_add, 0x3);
-            page_table = unsafe {
-                PageTable(Unique::new_unchecked(
-                    self.get_table_linear_add(d_offset) as *mut _
-                ))
-            };
+        let page_table_add: PhysicalAddress;
+        if !self.entry(d_offset).is_present() {
+            page_table_add = self.alloc_table()?;
+            // Pages are protected by their own entry, the table only has
+            // to be accessible enough
+            self.set_entry(
+                d_offset,
+                page_table_add,
+                PageFlags::KERNEL_DATA | flags & PageFlags::USER,
+            );
+            page_table = self.table(d_offset);
             page_table.clear();
         } else {
-            page_table = unsafe {
-                PageTable(Unique::new_unchecked(
-                    self.get_table_linear_add(d_offset) as *mut _
-                ))
-            };
+            assert!(
+                !self.entry(d_offset).is_large(),
+                "large page already present at index {} for virtual address {}",
+                d_offset,
+                virtual_page_address
+            );
+            if flags.contains(PageFlags::USER) && !self.entry(d_offset).is_user() {
+                let page_table_add = self.entry(d_offset).page_table_address();
+                self.set_entry(d_offset, page_table_add, PageFlags::USER_DATA);
+            }
+            page_table = self.table(d_offset);
         }
         assert!(
-            !page_table.ref_table()[t_offset].is_present(),
+            !page_table.entry(t_offset).is_present(),
             "page entry already present at index {} for virtual address {}",
             t_offset,
             virtual_page_address
@@ -203,7 +443,181 @@ impl PageDirectory {
         Ok(())
     }
 
+    /// Map a large page directly in the directory entry, without page table
+    ///
+    /// Large pages are 4 MiB, 2 MiB with PAE. Both addresses must be aligned
+    /// on that size, and nothing mapped in the range.
+    pub fn map_large_page(
+        &mut self,
+        physical_page_address: PhysicalAddress,
+        virtual_page_address: usize,
+        flags: PageFlags,
+    ) {
+        assert_eq!(
+            0,
+            physical_page_address & (large_page_size() as PhysicalAddress - 1),
+            "physical address is not aligned on a large page: {:#10x}",
+            physical_page_address
+        );
+        assert_eq!(
+            0,
+            virtual_page_address & (large_page_size() - 1),
+            "virtual address is not aligned on a large page: {:#10x}",
+            virtual_page_address
+        );
+
+        let d_offset = directory_index(virtual_page_address);
+        PageDirectory::assert_mappable(d_offset, virtual_page_address);
+        assert!(
+            !self.entry(d_offset).is_present(),
+            "directory entry already present at index {} for virtual address {}",
+            d_offset,
+            virtual_page_address
+        );
+        self.set_entry(
+            d_offset,
+            physical_page_address,
+            flags | PageFlags::LARGE_PAGE,
+        );
+    }
+
+    /// Flags of the page containing `virtual_address`
+    ///
+    /// The writable and user flags are only kept if they are also set in the
+    /// directory entry. Those of a large page are its directory entry ones.
+    /// `None` if the page is not mapped.
+    pub fn get_page_flags(&self, virtual_address: usize) -> Option<PageFlags> {
+        let d_offset = directory_index(virtual_address);
+        let t_offset = table_index(virtual_address);
+
+        let dir_entry = self.entry(d_offset);
+        if !dir_entry.is_present() {
+            return None;
+        }
+        if dir_entry.is_large() {
+            return Some(dir_entry.flags());
+        }
+        let entry = self.table(d_offset).entry(t_offset);
+        match entry.is_present() {
+            true => {
+                Some(entry.flags() & (dir_entry.flags() | !(PageFlags::WRITABLE | PageFlags::USER)))
+            }
+            false => None,
+        }
+    }
+
+    /// Physical address of the frame mapped at `virtual_address`
+    ///
+    /// Inside a large page, the 4k frame the address falls in.
+    pub fn get_page_frame(&self, virtual_address: usize) -> Option<PhysicalAddress> {
+        let d_offset = directory_index(virtual_address);
+        let t_offset = table_index(virtual_address);
+
+        let dir_entry = self.entry(d_offset);
+        if !dir_entry.is_present() {
+            return None;
+        }
+        if dir_entry.is_large() {
+            return Some(dir_entry.page_table_address() + (t_offset << 12) as PhysicalAddress);
+        }
+        let entry = self.table(d_offset).entry(t_offset);
+        match entry.is_present() {
+            true => Some(entry.page_frame_address()),
+            false => None,
+        }
+    }
+
+    /// Physical address `virtual_address` maps to, and the flags of its page
+    ///
+    /// The flags are those given by `get_page_flags`.
+    pub fn translate(&self, virtual_address: usize) -> Option<(PhysicalAddress, PageFlags)> {
+        let frame = self.get_page_frame(virtual_address)?;
+        let flags = self.get_page_flags(virtual_address)?;
+        Some((frame | (virtual_address & 0xFFF) as PhysicalAddress, flags))
+    }
+
+    /// Mapped pages of `range`, merged in runs of contiguous frames
+    pub fn mapped_regions(&self, range: Range<usize>) -> MappedRegions {
+        MappedRegions {
+            directory: self,
+            page: range.start >> 12,
+            last: (range.end >> 12) + (range.end & 0xFFF != 0) as usize,
+        }
+    }
+
+    /// The page table of the directory entry `index`, if present
+    ///
+    /// A large page has no page table.
+    pub fn page_table(&self, index: usize) -> Option<PageTable> {
+        if index >= directory_len() {
+            return None;
+        }
+        let entry = self.entry(index);
+        match entry.is_present() && !entry.is_large() {
+            true => Some(self.table(index)),
+            false => None,
+        }
+    }
+
+    /// Change the flags of a mapped page, keeping its frame
+    ///
+    /// The page stays present whatever `flags`. A page table shared with user
+    /// pages is made accessible to them, as in `map_pages`. A large page is
+    /// protected as a whole.
+    pub fn protect(
+        &mut self,
+        virtual_page_address: usize,
+        flags: PageFlags,
+    ) -> Result<(), VirtualMemoryError> {
+        let d_offset = directory_index(virtual_page_address);
+        let t_offset = table_index(virtual_page_address);
+
+        if self.entry(d_offset).is_large() {
+            let frame = self.entry(d_offset).page_table_address();
+            self.set_entry(
+                d_offset,
+                frame,
+                flags | PageFlags::PRESENT | PageFlags::LARGE_PAGE,
+            );
+            invalidate_page(virtual_page_address);
+            return Ok(());
+        }
+
+        let mut page_table = match self.page_table(d_offset) {
+            Some(page_table) if page_table.entry(t_offset).is_present() => page_table,
+            _ => return Err(VirtualMemoryError::PageNotMapped(virtual_page_address)),
+        };
+        if flags.contains(PageFlags::USER) && !self.entry(d_offset).is_user() {
+            let page_table_add = self.entry(d_offset).page_table_address();
+            self.set_entry(d_offset, page_table_add, PageFlags::USER_DATA);
+        }
+        let frame = page_table.entry(t_offset).page_frame_address();
+        page_table.set_entry(t_offset, frame, flags | PageFlags::PRESENT);
+        invalidate_page(virtual_page_address);
+        Ok(())
+    }
+
+    /// Unmap a page and free its frame, or all the frames of a large page
     pub fn unmap_pages(&mut self, virtual_page_address: usize) -> Result<(), VirtualMemoryError> {
+        let size = self.page_size(virtual_page_address);
+        let frame = self.unlink_page(virtual_page_address);
+        free_frames(frame, size)
+    }
+
+    /// Size of the page mapping `virtual_address`, bigger for a large page
+    fn page_size(&self, virtual_address: usize) -> usize {
+        match self.entry(directory_index(virtual_address)).is_large() {
+            true => large_page_size(),
+            false => PAGE_SIZE_4K,
+        }
+    }
+
+    /// Remove the mapping of a page, without freeing its frame
+    ///
+    /// A large page must be given by its first address, its whole directory
+    /// entry is cleared. Return the physical address of the frame that was
+    /// mapped.
+    pub fn unlink_page(&mut self, virtual_page_address: usize) -> PhysicalAddress {
         assert_eq!(
             0,
             virtual_page_address & 0xFFF,
@@ -211,44 +625,108 @@ impl PageDirectory {
             virtual_page_address
         );
 
-        let d_offset = virtual_page_address >> 22;
-        let t_offset = (virtual_page_address & 0x3FF000) >> 12;
+        let d_offset = directory_index(virtual_page_address);
+        let t_offset = table_index(virtual_page_address);
 
         assert!(
-            self.ref_dir()[d_offset].is_present(),
+            self.entry(d_offset).is_present(),
             "directory entry not present at index {} for virtual address {}",
             d_offset,
             virtual_page_address
         );
-        let mut page_table = unsafe {
-            PageTable(Unique::new_unchecked(
-                self.get_table_linear_add(d_offset) as *mut _
-            ))
-        };
+        if self.entry(d_offset).is_large() {
+            assert_eq!(
+                0, t_offset,
+                "virtual address is not aligned on a large page: {:#10x}",
+                virtual_page_address
+            );
+            let frame = self.entry(d_offset).page_table_address();
+            self.set_entry(d_offset, 0x0, PageFlags::empty());
+            invalidate_page(virtual_page_address);
+            return frame;
+        }
+        let mut page_table = self.table(d_offset);
         assert!(
-            page_table.ref_table()[t_offset].is_present(),
+            page_table.entry(t_offset).is_present(),
             "page entry not present at index {} for virtual address {}",
             t_offset,
             virtual_page_address
         );
-        BITMAP
-            .lock()
-            .free_frame(page_table.ref_table()[t_offset].page_frame_address())
-            .map_err(VirtualMemoryError::PhysicalMemoryError)?;
-        page_table.set_entry(t_offset, 0x0, 0x0);
+        let frame = page_table.entry(t_offset).page_frame_address();
+        page_table.set_entry(t_offset, 0x0, PageFlags::empty());
+        invalidate_page(virtual_page_address);
+        frame
+    }
+
+    /// Allocate the missing page tables of the directory entries in `range`
+    ///
+    /// Tables shared between several directories must exist before the
+    /// directories are created.
+    pub fn preallocate_tables(&mut self, range: Range<usize>) -> Result<(), VirtualMemoryError> {
+        for d_offset in range.filter(|&i| !self.entry(i).is_present()) {
+            let page_table_add = self.alloc_table()?;
+            self.set_entry(d_offset, page_table_add, PageFlags::KERNEL_DATA);
+            self.table(d_offset).clear();
+        }
+        Ok(())
+    }
+
+    /// Free every page, and page table, of the directory entries in `range`
+    pub fn clear_tables(&mut self, range: Range<usize>) -> Result<(), VirtualMemoryError> {
+        for d_offset in range.filter(|&i| self.entry(i).is_present()) {
+            if self.entry(d_offset).is_large() {
+                self.unmap_pages(page_address(d_offset, 0))?;
+                continue;
+            }
+            let page_table = self.table(d_offset);
+            for t_offset in 0..table_len() {
+                let entry = page_table.entry(t_offset);
+                if !entry.is_present() {
+                    continue;
+                }
+                frame_manager(|bitmap| bitmap.free_frame(entry.page_frame_address()))
+                    .map_err(VirtualMemoryError::PhysicalMemoryError)?;
+                invalidate_page(page_address(d_offset, t_offset));
+            }
+            frame_manager(|bitmap| bitmap.free_frame(self.entry(d_offset).page_table_address()))
+                .map_err(VirtualMemoryError::PhysicalMemoryError)?;
+            if self.is_enabled() {
+                invalidate_page(self.get_table_linear_add(d_offset));
+            }
+            self.set_entry(d_offset, 0x0, PageFlags::empty());
+        }
         Ok(())
     }
 }
 
+/// Free `size` bytes of contiguous frames starting at `frame`
+fn free_frames(frame: PhysicalAddress, size: usize) -> Result<(), VirtualMemoryError> {
+    frame_manager(|bitmap| {
+        for i in 0..size / PAGE_SIZE_4K {
+            bitmap
+                .free_frame(frame + (i * PAGE_SIZE_4K) as PhysicalAddress)
+                .map_err(VirtualMemoryError::PhysicalMemoryError)?;
+        }
+        Ok(())
+    })
+}
+
+/// Remove a page from the TLB
+///
+/// Must be done once the entry of a previously accessed page changed.
+fn invalidate_page(virtual_page_address: usize) {
+    unsafe {
+        asm!("invlpg [{}]", in(reg) virtual_page_address, options(nostack));
+    }
+}
+
 impl fmt::Display for PageDirectory {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for (idx, entry) in self
-            .ref_dir()
-            .iter()
-            .enumerate()
-            .filter(|(_, e)| e.is_present())
-        {
-            writeln!(f, "{:04}: {}", idx, entry)?
+        for idx in 0..directory_len() {
+            let entry = self.entry(idx);
+            if entry.is_present() {
+                writeln!(f, "{:04}: {}", idx, entry)?
+            }
         }
         Ok(())
     }
@@ -0,0 +1,135 @@
+//! Paging modes
+//!
+//! 32 bit paging: a page directory of 1024 entries, each pointing to a page
+//! table of 1024 entries or mapping a 4 MiB page. Entries are 32 bit, frames
+//! must lie below 4 GiB.
+//!
+//! PAE paging: a page directory pointer table of 4 entries, each pointing to
+//! a page directory of 512 entries, pointing to page tables of 512 entries or
+//! mapping 2 MiB pages. Entries are 64 bit, frames can lie above 4 GiB and
+//! pages can be made non executable. The four directories are contiguous in
+//! the recursive mapping, they are handled as one directory of 2048 entries.
+//!
+//! The mode is chosen at boot, before paging is set up, and never changes.
+
+use core::ops::Range;
+use core::str::FromStr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PagingMode {
+    /// The kernel keeps running on the bootstrap mapping
+    Disabled,
+    /// 32 bit paging
+    Legacy,
+    /// Physical address extension
+    Pae,
+}
+
+/// The `paging` boot option, a boolean or `pae`
+impl FromStr for PagingMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<PagingMode, ()> {
+        match s {
+            "off" | "false" | "no" | "0" => Ok(PagingMode::Disabled),
+            "" | "on" | "true" | "yes" | "1" => Ok(PagingMode::Legacy),
+            "pae" => Ok(PagingMode::Pae),
+            _ => Err(()),
+        }
+    }
+}
+
+static PAE: AtomicBool = AtomicBool::new(false);
+static NO_EXECUTE: AtomicBool = AtomicBool::new(false);
+
+/// CPUID leaf 1, EDX: physical address extension
+const CPUID_PAE: u32 = 1 << 6;
+/// CPUID leaf 0x80000001, EDX: execute disable bit
+const CPUID_NO_EXECUTE: u32 = 1 << 20;
+
+/// The CPU supports PAE paging
+pub fn pae_supported() -> bool {
+    unsafe { core::arch::x86::__cpuid(1).edx & CPUID_PAE != 0 }
+}
+
+/// The CPU supports non executable pages, with PAE paging
+pub fn no_execute_supported() -> bool {
+    unsafe {
+        core::arch::x86::__cpuid(0x80000000).eax >= 0x80000001
+            && core::arch::x86::__cpuid(0x80000001).edx & CPUID_NO_EXECUTE != 0
+    }
+}
+
+/// Use PAE paging from now on, with non executable pages if supported
+///
+/// Must be called before any paging structure is built.
+pub(super) fn select_pae() {
+    PAE.store(true, Ordering::Relaxed);
+    NO_EXECUTE.store(no_execute_supported(), Ordering::Relaxed);
+}
+
+pub fn is_pae() -> bool {
+    PAE.load(Ordering::Relaxed)
+}
+
+/// Non executable pages are enforced
+pub fn has_no_execute() -> bool {
+    NO_EXECUTE.load(Ordering::Relaxed)
+}
+
+/// Number of directory entries
+pub fn directory_len() -> usize {
+    match is_pae() {
+        true => 2048,
+        false => 1024,
+    }
+}
+
+/// Number of page table entries
+pub fn table_len() -> usize {
+    match is_pae() {
+        true => 512,
+        false => 1024,
+    }
+}
+
+/// Size of the memory mapped by a directory entry, and of a large page
+pub fn large_page_size() -> usize {
+    table_len() << 12
+}
+
+/// Directory entry of a virtual address
+pub fn directory_index(address: usize) -> usize {
+    address / large_page_size()
+}
+
+/// Page table entry of a virtual address
+pub fn table_index(address: usize) -> usize {
+    (address >> 12) & (table_len() - 1)
+}
+
+/// First virtual address of the pages of a page table entry
+pub fn page_address(d_offset: usize, t_offset: usize) -> usize {
+    d_offset * large_page_size() + (t_offset << 12)
+}
+
+/// Directory entries pointing to the directory frames themselves
+///
+/// Every page table is reached above the first one, then the directory.
+pub fn recursive_entries() -> Range<usize> {
+    match is_pae() {
+        true => 2044..2048,
+        false => 1023..1024,
+    }
+}
+
+/// Virtual address of the page tables, through the recursive entries
+pub fn tables_address() -> usize {
+    page_address(recursive_entries().start, 0)
+}
+
+/// Virtual address of the directory, through the recursive entries
+pub fn directory_address() -> usize {
+    tables_address() + (recursive_entries().start << 12)
+}
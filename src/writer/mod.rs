@@ -6,34 +6,77 @@
 //! - A new line is added when the current is full
 //! - The cursor can be moved along the line to write at specific position
 //! - Characters can be removed from screen with backspace
+//! - Printed text can be mirrored on the serial port
 
 mod screen_writer;
 
 use spin::Mutex;
 
 use self::screen_writer::VGAScreen;
+use crate::virtual_memory_management::physical_to_kernel;
 use core::convert::TryInto;
 use MultibootInfo;
 
 /// Unique enty point to write characters on the VGA screen
+///
+/// Only locked through `screen` once initialized, printing must not find it
+/// held by a preempted task.
 pub static WRITER: Mutex<Option<VGAScreen>> = Mutex::new(None);
 
+/// Access the screen, with interrupts disabled
+pub fn screen<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut VGAScreen) -> R,
+{
+    crate::idt::without_interrupts(|| f(WRITER.lock().as_mut().unwrap()))
+}
+
 use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Also print on the serial port
+///
+/// The screen keeps everything, the shell reads its commands there.
+static SERIAL_CONSOLE: AtomicBool = AtomicBool::new(false);
+
+/// Where printed text goes
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Console {
+    Vga,
+    /// Mirrored on the serial port, which must be initialized
+    Serial,
+}
+
+pub fn set_console(console: Console) {
+    SERIAL_CONSOLE.store(console == Console::Serial, Ordering::Relaxed);
+}
 
 pub fn init(multiboot: MultibootInfo) {
     let tag = multiboot.get_framebuffer().unwrap();
 
+    // Low memory, mapped in the kernel space
     WRITER.lock().replace(VGAScreen::new(
-        tag.framebuffer_addr.try_into().unwrap(),
+        physical_to_kernel(tag.framebuffer_addr.try_into().unwrap()),
         tag.framebuffer_width.try_into().unwrap(),
         tag.framebuffer_height.try_into().unwrap(),
     ));
-    WRITER.lock().as_mut().unwrap().clear_screen();
+    screen(|screen| screen.clear_screen());
 }
 
 pub fn print_args(args: fmt::Arguments) {
     use core::fmt::Write;
-    WRITER.lock().as_mut().unwrap().write_fmt(args).unwrap();
+    // An interrupt handler printing while the lock is held would deadlock
+    crate::idt::without_interrupts(|| {
+        screen(|screen| screen.write_fmt(args)).unwrap();
+        if SERIAL_CONSOLE.load(Ordering::Relaxed) {
+            crate::serial::SERIAL
+                .lock()
+                .as_mut()
+                .unwrap()
+                .write_fmt(args)
+                .unwrap();
+        }
+    });
 }
 
 macro_rules! println {